@@ -0,0 +1,131 @@
+//! Run with `cargo bench --features bench`, which exposes the internals measured here
+
+#[macro_use]
+extern crate criterion;
+extern crate termbuf;
+
+use criterion::Criterion;
+use std::hint::black_box;
+use std::io::sink;
+use std::mem;
+use termbuf::{CellBuffer, Color, RenderMode, TermBuf, TermCell, TermSize};
+
+/// The size of a large dashboard
+const SIZE: TermSize = TermSize {
+    width: 300,
+    height: 100,
+};
+
+fn fill(c: &mut Criterion) {
+    let mut buffer = CellBuffer::new(SIZE.width, SIZE.height);
+    let mut cell = TermCell::with_char('x');
+    cell.bg = Some(Color::Blue);
+    c.bench_function("fill", |b| {
+        b.iter(|| buffer.fill(buffer.area(), black_box(cell)))
+    });
+}
+
+fn print(c: &mut Criterion) {
+    let mut buffer = CellBuffer::new(SIZE.width, SIZE.height);
+    let line = "The quick brown fox jumps over the lazy dog. ".repeat(7);
+    c.bench_function("print", |b| {
+        b.iter(|| {
+            for y in 0..SIZE.height {
+                buffer.print(0, y, black_box(&line));
+            }
+        })
+    });
+}
+
+fn flush(c: &mut Criterion) {
+    let mut term = TermBuf::with_writer(sink(), SIZE, RenderMode::Color);
+    let colors = [Color::Red, Color::Green];
+    let mut frame = 0;
+    c.bench_function("flush every row changed", |b| {
+        b.iter(|| {
+            // Alternate the colors so every row differs from the last frame
+            frame += 1;
+            for y in 0..SIZE.height {
+                term.string_builder(0, y, &format!("row {} frame {}", y, frame))
+                    .fg(colors[frame % 2])
+                    .draw();
+            }
+            term.flush().unwrap();
+        })
+    });
+
+    c.bench_function("flush unchanged", |b| b.iter(|| term.flush().unwrap()));
+}
+
+/// Flushes changing one cell at the end of every row, so each whole row is compared but little
+/// is written
+fn diff(c: &mut Criterion) {
+    let mut term = TermBuf::with_writer(sink(), SIZE, RenderMode::Color);
+    for y in 0..SIZE.height {
+        term.string_builder(0, y, &"styled text ".repeat(25))
+            .fg(Color::Rgb(200, 100, 0))
+            .bg(Color::AnsiValue(236))
+            .draw();
+    }
+    let mut frame = 0;
+    c.bench_function("diff one cell per row", |b| {
+        b.iter(|| {
+            frame += 1;
+            let ch = if frame % 2 == 0 { 'a' } else { 'b' };
+            for y in 0..SIZE.height {
+                term.set_char(SIZE.width - 1, y, ch);
+            }
+            term.flush().unwrap();
+        })
+    });
+}
+
+/// Compares every row of two frames that differ in the last cell of each row, with the cells
+/// stored as `TermCell`s and as the packed cells of a `CellBuffer`
+fn diff_rows(c: &mut Criterion) {
+    let line = "styled text ".repeat(25);
+    let mut old = CellBuffer::new(SIZE.width, SIZE.height);
+    for y in 0..SIZE.height {
+        old.string_builder(0, y, &line)
+            .fg(Color::Rgb(200, 100, 0))
+            .bg(Color::AnsiValue(236))
+            .draw();
+    }
+    let mut new = old.clone();
+    for y in 0..SIZE.height {
+        new.set_char(SIZE.width - 1, y, 'x');
+    }
+    let unpack = |buffer: &CellBuffer| -> Vec<Vec<TermCell>> {
+        (0..SIZE.height)
+            .map(|y| (0..SIZE.width).map(|x| buffer.get(x, y).unwrap()).collect())
+            .collect()
+    };
+    let (old_cells, new_cells) = (unpack(&old), unpack(&new));
+
+    println!(
+        "{}x{} cells: {} bytes as TermCell, {} bytes packed",
+        SIZE.width,
+        SIZE.height,
+        SIZE.width * SIZE.height * mem::size_of::<TermCell>(),
+        new.cells_size()
+    );
+    c.bench_function("diff rows of TermCell", |b| {
+        b.iter(|| {
+            black_box(&old_cells)
+                .iter()
+                .zip(black_box(&new_cells))
+                .filter(|&(old_row, new_row)| old_row != new_row)
+                .count()
+        })
+    });
+    c.bench_function("diff rows of packed cells", |b| {
+        b.iter(|| {
+            (0..SIZE.height)
+                .filter(|&y| !black_box(&new).same_row(black_box(&old), y))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, fill, print, flush, diff, diff_rows);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+#![allow(unknown_lints, non_upper_case_globals)]
+
+/// The horizontal alignment of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    /// Against the left edge
+    #[default]
+    Left,
+    /// In the middle, rounding to the left
+    Center,
+    /// Against the right edge
+    Right,
+}
+
+/// The characters used to draw a border
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BorderGlyphs {
+    /// The top left corner
+    pub top_left: char,
+    /// The top right corner
+    pub top_right: char,
+    /// The bottom left corner
+    pub bottom_left: char,
+    /// The bottom right corner
+    pub bottom_right: char,
+    /// Top and bottom sides, and horizontal lines
+    pub horizontal: char,
+    /// Left and right sides, and vertical lines
+    pub vertical: char,
+}
+
+/// The look of box borders and lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BorderStyle {
+    /// `┌─┐│└┘`
+    #[default]
+    Plain,
+    /// `╭─╮│╰╯`
+    Rounded,
+    /// `╔═╗║╚╝`
+    Double,
+    /// `┏━┓┃┗┛`
+    Heavy,
+    /// `┌┄┐┆└┘`
+    Dashed,
+    /// `+-+|++`
+    Ascii,
+    /// Any set of characters
+    Custom(BorderGlyphs),
+}
+
+impl BorderStyle {
+    /// Returns the characters of this style
+    pub fn glyphs(&self) -> BorderGlyphs {
+        let glyphs = |chars: [char; 6]| BorderGlyphs {
+            top_left: chars[0],
+            top_right: chars[1],
+            bottom_left: chars[2],
+            bottom_right: chars[3],
+            horizontal: chars[4],
+            vertical: chars[5],
+        };
+        match self {
+            BorderStyle::Plain => glyphs(['┌', '┐', '└', '┘', '─', '│']),
+            BorderStyle::Rounded => glyphs(['╭', '╮', '╰', '╯', '─', '│']),
+            BorderStyle::Double => glyphs(['╔', '╗', '╚', '╝', '═', '║']),
+            BorderStyle::Heavy => glyphs(['┏', '┓', '┗', '┛', '━', '┃']),
+            BorderStyle::Dashed => glyphs(['┌', '┐', '└', '┘', '┄', '┆']),
+            BorderStyle::Ascii => glyphs(['+', '+', '+', '+', '-', '|']),
+            BorderStyle::Custom(glyphs) => *glyphs,
+        }
+    }
+}
+
+bitflags! {
+    /// The sides of a box to draw a border on
+    pub struct Sides: u8 {
+        const Top =    0b0001;
+        const Bottom = 0b0010;
+        const Left =   0b0100;
+        const Right =  0b1000;
+    }
+}
+
+impl Default for Sides {
+    fn default() -> Sides {
+        Sides::all()
+    }
+}
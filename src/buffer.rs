@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use builder::*;
+use grid::{blank_half, split_wide, Grid};
+use layer::place;
+use link::{self, LinkTable};
+use packed::PackedCell;
+use {
+    display_width, Color, Gradient, Inherit, LinkId, Rect, Sides, Style, StylePatch, TermCell,
+    Theme,
+};
+
+/// A grid of cells with the drawing api of [`TermBuf`][::TermBuf], but no terminal
+///
+/// Draw expensive content once into a `CellBuffer`, then copy it to the screen with
+/// [`blit`][CellBuffer::blit]. Code that draws to any `CellBuffer` can draw to the screen through
+/// [`TermBuf::buffer_mut`][::TermBuf::buffer_mut]
+#[derive(Debug)]
+pub struct CellBuffer {
+    /// The cells
+    pub(crate) cells: Grid,
+    /// The cell cleared cells are set to
+    blank: TermCell,
+    /// The theme used by builders for roles, shared with the other buffers of a `TermBuf`
+    pub(crate) theme: Arc<Theme>,
+    /// The targets of the links in the cells, shared with the other buffers of a `TermBuf`
+    pub(crate) links: Arc<Mutex<LinkTable>>,
+    /// Whether boxes and lines merge with box drawing characters they are drawn over
+    merge_junctions: bool,
+}
+
+/// Clones get their own copy of the link table, so their ids stay valid when the original's
+/// table is compacted, and blitting them remaps the ids
+impl Clone for CellBuffer {
+    fn clone(&self) -> CellBuffer {
+        CellBuffer {
+            cells: self.cells.clone(),
+            blank: self.blank,
+            theme: Arc::clone(&self.theme),
+            links: Arc::new(Mutex::new(link::lock(&self.links).clone())),
+            merge_junctions: self.merge_junctions,
+        }
+    }
+}
+
+impl CellBuffer {
+    /// Creates a buffer of empty cells
+    pub fn new(width: usize, height: usize) -> CellBuffer {
+        CellBuffer::with_blank(width, height, TermCell::empty())
+    }
+
+    /// Creates a buffer of transparent cells, which can be blitted over other content
+    ///
+    /// Clearing it makes the cells transparent again
+    pub fn transparent(width: usize, height: usize) -> CellBuffer {
+        CellBuffer::with_blank(width, height, TermCell::transparent())
+    }
+
+    fn with_blank(width: usize, height: usize, blank: TermCell) -> CellBuffer {
+        CellBuffer {
+            cells: Grid::new(width, height, blank),
+            blank,
+            theme: Arc::new(Theme::basic()),
+            links: Arc::default(),
+            merge_junctions: false,
+        }
+    }
+
+    /// Takes the theme, link table and junction merging of another buffer, used to keep the
+    /// layers of a `TermBuf` in step with its base buffer
+    pub(crate) fn share_settings(&mut self, base: &CellBuffer) {
+        if !Arc::ptr_eq(&self.theme, &base.theme) {
+            self.theme = Arc::clone(&base.theme);
+        }
+        if !Arc::ptr_eq(&self.links, &base.links) {
+            self.links = Arc::clone(&base.links);
+        }
+        self.merge_junctions = base.merge_junctions;
+    }
+
+    /// Gets the width in cells
+    pub fn width(&self) -> usize {
+        self.cells.width()
+    }
+
+    /// Gets the height in cells
+    pub fn height(&self) -> usize {
+        self.cells.height()
+    }
+
+    /// Returns the area covered by the buffer
+    pub fn area(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    /// Returns whether row `y` holds the same cells in both buffers
+    ///
+    /// The cells are compared as stored, without unpacking them, and links by id. Rows outside
+    /// either buffer are never the same. Only built for the tests and the benches
+    #[cfg(any(test, feature = "bench"))]
+    #[doc(hidden)]
+    pub fn same_row(&self, other: &CellBuffer, y: usize) -> bool {
+        match (self.cells.row(y), other.cells.row(y)) {
+            (Some(row), Some(other_row)) => row == other_row,
+            _ => false,
+        }
+    }
+
+    /// Returns the bytes the cells take up, not counting the link table or theme. Only built for
+    /// the tests and the benches
+    #[cfg(any(test, feature = "bench"))]
+    #[doc(hidden)]
+    pub fn cells_size(&self) -> usize {
+        self.cells.rows().map(::std::mem::size_of_val).sum()
+    }
+
+    /// Changes the size of the buffer, clearing every cell
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.cells.resize(width, height, self.blank);
+    }
+
+    /// Gets a cell
+    pub fn get(&self, x: usize, y: usize) -> Option<TermCell> {
+        self.cells.get(x, y)
+    }
+
+    /// Writes an entire string
+    pub fn print(&mut self, x: usize, y: usize, s: &str) {
+        let mut x = x;
+        for ch in s.chars() {
+            self.set_char(x, y, ch);
+            x += display_width(ch);
+        }
+    }
+
+    /// Writes a single char
+    pub fn set_char(&mut self, x: usize, y: usize, ch: char) {
+        self.cells.set(x, y, TermCell::with_char(ch));
+    }
+
+    /// Writes a single cell
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: TermCell) {
+        self.cells.set(x, y, cell);
+    }
+
+    /// Replaces the forground of a cell
+    pub fn set_cell_fg(&mut self, x: usize, y: usize, fg: Color) {
+        self.cells.update(x, y, |old_cell| old_cell.fg = Some(fg));
+    }
+
+    /// Replaces the background of a cell
+    pub fn set_cell_bg(&mut self, x: usize, y: usize, bg: Color) {
+        self.cells.update(x, y, |old_cell| old_cell.bg = Some(bg));
+    }
+
+    /// Replaces the underline color of a cell
+    pub fn set_cell_underline_color(&mut self, x: usize, y: usize, color: Color) {
+        self.cells
+            .update(x, y, |old_cell| old_cell.underline_color = Some(color));
+    }
+
+    /// Replaces the hyperlink of a cell
+    pub fn set_cell_link(&mut self, x: usize, y: usize, link: Option<LinkId>) {
+        self.cells.update(x, y, |old_cell| old_cell.link = link);
+    }
+
+    /// Replaces the style of a cell
+    pub fn set_cell_style(&mut self, x: usize, y: usize, style: Style) {
+        self.cells.update(x, y, |old_cell| old_cell.style = style);
+    }
+
+    /// Sets every cell in an area to `cell`
+    ///
+    /// Wide characters are repeated every other column, with a space where one would not fit
+    pub fn fill(&mut self, rect: Rect, cell: TermCell) {
+        for y in rect.y..(rect.y + rect.height).min(self.height()) {
+            self.cells.fill_span(y, rect.x, rect.x + rect.width, cell);
+        }
+    }
+
+    /// Empties every cell in an area
+    ///
+    /// Cells of transparent buffers and layers are made transparent
+    pub fn clear_region(&mut self, rect: Rect) {
+        let blank = self.blank;
+        self.fill(rect, blank);
+    }
+
+    /// Empties every cell
+    pub fn clear(&mut self) {
+        self.cells.fill(self.blank);
+    }
+
+    /// Applies a patch to a cell, keeping its content and anything the patch doesn't change
+    pub fn patch_cell(&mut self, x: usize, y: usize, patch: &StylePatch) {
+        self.cells.update(x, y, |old_cell| patch.apply(old_cell));
+    }
+
+    /// Applies a patch to every cell in an area
+    pub fn patch_region(&mut self, rect: Rect, patch: &StylePatch) {
+        let bottom = rect.y.saturating_add(rect.height).min(self.height());
+        let right = rect.x.saturating_add(rect.width).min(self.width());
+        for y in rect.y..bottom {
+            for x in rect.x..right {
+                self.patch_cell(x, y, patch);
+            }
+        }
+    }
+
+    /// Replaces the background of every cell in an area with a gradient
+    pub fn set_bg_gradient(&mut self, rect: Rect, gradient: &Gradient) {
+        // Only the visible part is drawn, but the colors are spread over the whole area
+        let height = rect.height.min(self.height().saturating_sub(rect.y));
+        let width = rect.width.min(self.width().saturating_sub(rect.x));
+        for y in 0..height {
+            for x in 0..width {
+                let color = gradient.at_cell(x, y, rect.width, rect.height);
+                self.set_cell_bg(rect.x + x, rect.y + y, color);
+            }
+        }
+    }
+
+    /// Moves the content of an area up by `n` rows, or down if `n` is negative
+    ///
+    /// Rows moved out of the area are lost, and the rows left behind are cleared
+    pub fn scroll_region(&mut self, rect: Rect, n: isize) {
+        self.cells.scroll(rect, n, self.blank);
+    }
+
+    /// Copies an area of another buffer, placing its top left corner at `x`, `y`
+    ///
+    /// The area is clipped to both buffers. Wide characters cut in half by the edges of the area,
+    /// or by the edge of this buffer, are replaced with spaces, as are those in this buffer whose
+    /// right half is drawn over. Transparent cells, and attributes that cells inherit, leave what
+    /// was below showing through. Links keep their targets, but may get other ids
+    pub fn blit(&mut self, buffer: &CellBuffer, src: Rect, x: usize, y: usize) {
+        if Arc::ptr_eq(&self.links, &buffer.links) {
+            blit_cells(&mut self.cells, &buffer.cells, src, x, y, Some);
+            return;
+        }
+        // The ids of the other buffer mean nothing here, so its targets are added to this
+        // buffer's table as cells are copied. The tables are never locked together, so blits
+        // can't deadlock
+        let (from, to) = (&buffer.links, &self.links);
+        let mut ids = HashMap::new();
+        let remap = |id| {
+            *ids.entry(id).or_insert_with(|| {
+                let target = link::lock(from).get(id).map(str::to_owned);
+                target.map(|target| link::lock(to).intern(&target))
+            })
+        };
+        blit_cells(&mut self.cells, &buffer.cells, src, x, y, remap);
+    }
+
+    /// Returns the id of a hyperlink target, for use in [`TermCell::link`]
+    pub fn intern_link(&mut self, target: &str) -> LinkId {
+        link::lock(&self.links).intern(target)
+    }
+
+    /// Returns the target of a hyperlink id
+    pub fn link_target(&self, link: LinkId) -> Option<String> {
+        link::lock(&self.links).get(link).map(str::to_owned)
+    }
+
+    /// Gets the theme used by builders for roles
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Gets the theme mutably, to change individual roles
+    pub fn theme_mut(&mut self) -> &mut Theme {
+        Arc::make_mut(&mut self.theme)
+    }
+
+    /// Replaces the theme, returning the old one
+    ///
+    /// Only affects drawing done afterwards, existing cells keep their colors and styles
+    pub fn set_theme(&mut self, theme: Theme) -> Theme {
+        let old = ::std::mem::replace(&mut self.theme, Arc::new(theme));
+        Arc::try_unwrap(old).unwrap_or_else(|old| (*old).clone())
+    }
+
+    /// Returns a builder for a styled cell, whose roles come from the theme
+    pub fn cell_builder(&self, content: char) -> CellBuilder {
+        CellBuilder::with_theme(content, Arc::clone(&self.theme))
+    }
+
+    /// Writes a single char with color builder
+    pub fn char_builder(&mut self, x: usize, y: usize, ch: char) -> StyleCellBuilder<'_> {
+        StyleCellBuilder::new(
+            &mut self.cells,
+            &self.theme,
+            &self.links,
+            x,
+            y,
+            ch.to_string(),
+        )
+    }
+
+    /// Writes a string with color builder
+    pub fn string_builder(&mut self, x: usize, y: usize, s: &str) -> StyleCellBuilder<'_> {
+        StyleCellBuilder::new(
+            &mut self.cells,
+            &self.theme,
+            &self.links,
+            x,
+            y,
+            s.to_owned(),
+        )
+    }
+
+    /// Draws a simple (unstyled) unicode box
+    pub fn draw_box(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.box_builder(x, y, width, height).draw();
+    }
+
+    /// Sets whether boxes and lines merge with box drawing characters they are drawn over
+    ///
+    /// When enabled, a corner drawn over a line becomes the matching tee or cross, so boxes
+    /// sharing an edge and lines crossing boxes join up. Disabled by default, builders can
+    /// override it with `merge_junctions`
+    pub fn set_merge_junctions(&mut self, merge: bool) {
+        self.merge_junctions = merge;
+    }
+
+    /// Creates a builder to draw a popup box, centered in the buffer
+    ///
+    /// The size is that of the interior like [`box_builder`][CellBuffer::box_builder]. The
+    /// interior is cleared and a shadow is cast on the cells to the right and below
+    pub fn popup(&mut self, width: usize, height: usize) -> BoxBuilder<'_> {
+        let area = self.area();
+        self.popup_in(area, width, height)
+    }
+
+    /// Creates a builder to draw a popup box, centered in an area
+    ///
+    /// Boxes larger than the area are shrunk to fit it, and the shadow is cut off at its edges.
+    /// Nothing is drawn in areas too small for the border, see [`popup`][CellBuffer::popup]
+    pub fn popup_in(&mut self, rect: Rect, width: usize, height: usize) -> BoxBuilder<'_> {
+        let outer = rect.centered(width.saturating_add(2), height.saturating_add(2));
+        // The box is shrunk with the area when it does not fit
+        let mut builder = self.box_builder(
+            outer.x,
+            outer.y,
+            outer.width.saturating_sub(2),
+            outer.height.saturating_sub(2),
+        );
+        builder.clear_interior().shadow(true).shadow_area(rect);
+        if outer.width < 2 || outer.height < 2 {
+            builder.sides(Sides::empty()).shadow(false);
+        }
+        builder
+    }
+
+    /// Creates a builder to draw a styled box
+    pub fn box_builder(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> BoxBuilder<'_> {
+        BoxBuilder::new(
+            &mut self.cells,
+            &self.theme,
+            x,
+            y,
+            width,
+            height,
+            self.merge_junctions,
+        )
+    }
+
+    /// Draws a simple (unstyled) vertical line
+    pub fn draw_vertical_line(&mut self, x: usize, y: usize, len: usize) {
+        self.line_builder(x, y, len).vertical().draw();
+    }
+
+    /// Draws a simple (unstyled) horizontal line
+    pub fn draw_horiztonal_line(&mut self, x: usize, y: usize, len: usize) {
+        self.line_builder(x, y, len).horizontal().draw();
+    }
+
+    /// Creates a builder to draw a styled line
+    pub fn line_builder(&mut self, x: usize, y: usize, len: usize) -> LineBuilder<'_> {
+        LineBuilder::new(
+            &mut self.cells,
+            &self.theme,
+            x,
+            y,
+            len,
+            self.merge_junctions,
+        )
+    }
+}
+
+/// Copies an area of `src` to `dest`, giving linked cells the ids `link` maps theirs to, see
+/// [`CellBuffer::blit`]
+pub(crate) fn blit_cells<F>(
+    dest: &mut Grid,
+    src: &Grid,
+    rect: Rect,
+    x: usize,
+    y: usize,
+    mut link: F,
+) where
+    F: FnMut(LinkId) -> Option<LinkId>,
+{
+    for row in 0..rect.height {
+        let (from, to) = match (src.row(rect.y + row), dest.row_mut(y + row)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => break,
+        };
+        let end = (rect.x + rect.width).min(from.len());
+        // The source columns covered by the right half of a wide character already copied
+        let mut covered = 0;
+        for sx in rect.x..end {
+            let dx = x + sx - rect.x;
+            if dx >= to.len() {
+                break;
+            }
+            if sx < covered {
+                continue;
+            }
+            let mut above = from[sx].unpack();
+            if sx == rect.x && sx > 0 && from[sx - 1].width() > 1 {
+                // The right half of a wide character cut by the left edge of the area
+                above = blank_half(from[sx - 1]).unpack();
+            }
+            above.link = above.link.and_then(&mut link);
+            if above.inherit.contains(Inherit::Content) {
+                to[dx] = PackedCell::new(&place(&to[dx].unpack(), &above));
+                continue;
+            }
+            // Wide characters the cell is drawn over half of are blanked
+            split_wide(to, dx);
+            let mut cell = place(&to[dx].unpack(), &above);
+            let width = cell.width as usize;
+            if sx + width > end || dx + width > to.len() {
+                cell.content = ' ';
+                cell.width = 1;
+            }
+            split_wide(to, dx + cell.width as usize);
+            covered = sx + cell.width as usize;
+            to[dx] = PackedCell::new(&cell);
+        }
+    }
+}
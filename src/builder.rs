@@ -1,11 +1,74 @@
-use {display_width, Color, Style, TermCell};
+use std::sync::{Arc, Mutex};
+
+use border::{Align, BorderStyle, Sides};
+use grid::Grid;
+use junction::{has_arm, merge_junction_end};
+use link::{self, LinkTable};
+use {
+    display_width, Color, Gradient, Inherit, LinkId, Rect, Style, StylePatch, TermCell, Theme,
+    ThemeStyle,
+};
+
+fn set_cell(buf: &mut Grid, cell: TermCell, x: usize, y: usize) {
+    buf.set(x, y, cell);
+}
+
+/// Writes a box drawing cell, merging it with the existing character if `merge` is set
+fn set_border_cell(buf: &mut Grid, cell: TermCell, x: usize, y: usize, merge: bool) {
+    set_line_end_cell(buf, cell, x, y, merge, [true; 4]);
+}
 
-fn set_cell<'a>(buf: &'a mut Vec<Vec<TermCell>>, cell: TermCell, x: usize, y: usize) {
-    if let Some(line) = buf.get_mut(y) {
-        if let Some(mut old_ch) = line.get_mut(x) {
-            *old_ch = cell
+/// Writes a box drawing cell, only merging the lines leaving in the `open` directions
+fn set_line_end_cell(
+    buf: &mut Grid,
+    mut cell: TermCell,
+    x: usize,
+    y: usize,
+    merge: bool,
+    open: [bool; 4],
+) {
+    // Lines of the old character are only kept where the neighbouring cell has a line leading
+    // back, so drawing order doesn't change the junction
+    let neighbours = [
+        y.checked_sub(1).map(|y| (x, y)),
+        Some((x + 1, y)),
+        Some((x, y + 1)),
+        x.checked_sub(1).map(|x| (x, y)),
+    ];
+    let mut kept = [false; 4];
+    for (i, neighbour) in neighbours.iter().enumerate() {
+        kept[i] = neighbour
+            .and_then(|(x, y)| buf.get(x, y))
+            .is_some_and(|neighbour| has_arm(neighbour.content, (i + 2) % 4));
+    }
+    buf.update(x, y, |old_cell| {
+        if merge {
+            cell.content = merge_junction_end(old_cell.content, cell.content, open, kept);
         }
+        *old_cell = cell;
+    });
+}
+
+/// Shortens text to fit in `max` cells, ending it with an ellipsis if anything was cut
+fn truncate(text: &str, max: usize) -> String {
+    let total: usize = text.chars().map(display_width).sum();
+    if total <= max {
+        return text.to_owned();
+    }
+    let mut truncated = String::new();
+    let mut width = 0;
+    for ch in text.chars() {
+        let ch_width = display_width(ch);
+        if width + ch_width + 1 > max {
+            break;
+        }
+        truncated.push(ch);
+        width += ch_width;
+    }
+    if max > 0 {
+        truncated.push('…');
     }
+    truncated
 }
 
 macro_rules! impl_style_fns {
@@ -22,6 +85,18 @@ macro_rules! impl_style_fns {
             self
         }
 
+        /// Sets the underline color
+        pub fn underline_color(&mut self, color: Color) -> &mut $return_type {
+            self.underline_color = Some(color);
+            self
+        }
+
+        /// Sets the underline color to an Option
+        pub fn maybe_underline_color(&mut self, color: Option<Color>) -> &mut $return_type {
+            self.underline_color = color;
+            self
+        }
+
         /// Sets the forground color to an Option
         pub fn maybe_fg(&mut self, color: Option<Color>) -> &mut $return_type {
             self.fg = color;
@@ -36,32 +111,92 @@ macro_rules! impl_style_fns {
 
         /// Adds a style
         pub fn style(&mut self, style: Style) -> &mut $return_type {
-            let styles = self.style.unwrap_or_default();
-            self.style = Some(styles | style);
+            self.style |= style;
             self
         }
 
         /// Adds multiple styles
         pub fn styles(&mut self, styles: Style) -> &mut $return_type {
-            let old_styles = self.style.unwrap_or_default();
-            self.style = Some(old_styles | styles);
+            self.style |= styles;
             self
         }
 
-        /// Sets all styles
+        /// Removes styles
+        pub fn remove_styles(&mut self, styles: Style) -> &mut $return_type {
+            self.style -= styles;
+            self
+        }
+
+        /// Sets all styles, `None` is the same as no styles
         pub fn maybe_styles(&mut self, styles: Option<Style>) -> &mut $return_type {
-            self.style = styles;
+            self.style = styles.unwrap_or_default();
+            self
+        }
+
+        /// Sets the styles to be taken from the layer below instead of this builder's styles
+        pub fn inherit_style(&mut self) -> &mut $return_type {
+            self.inherit |= Inherit::Style;
+            self
+        }
+
+        /// Sets the forground color to be taken from the layer below
+        pub fn inherit_fg(&mut self) -> &mut $return_type {
+            self.inherit |= Inherit::Fg;
+            self
+        }
+
+        /// Sets the background color to be taken from the layer below
+        pub fn inherit_bg(&mut self) -> &mut $return_type {
+            self.inherit |= Inherit::Bg;
+            self
+        }
+
+        /// Sets the colors and styles from a theme entry
+        ///
+        /// Only what the entry sets is replaced, so colors and styles set before are kept when the
+        /// entry leaves them out. Whatever is set last wins
+        pub fn themed(&mut self, theme_style: &ThemeStyle) -> &mut $return_type {
+            if let Some(fg) = theme_style.fg {
+                self.fg = Some(fg);
+            }
+            if let Some(bg) = theme_style.bg {
+                self.bg = Some(bg);
+            }
+            if let Some(style) = theme_style.style {
+                self.style = style;
+            }
+            self
+        }
+    };
+}
+
+macro_rules! impl_role_fn {
+    ($return_type:ty) => {
+        /// Sets the colors and styles from a role of the current theme
+        ///
+        /// Unknown roles leave the colors and styles unchanged, see `themed`
+        pub fn role(&mut self, role: &str) -> &mut $return_type {
+            if let Some(theme_style) = self.theme.get(role) {
+                let theme_style = *theme_style;
+                self.themed(&theme_style);
+            }
             self
         }
     };
 }
 
 /// A builder to construct a styled cell
+///
+/// Create one with `new`, or with [`cell_builder`][::CellBuffer::cell_builder] to use the roles
+/// of a buffer's theme
 pub struct CellBuilder {
     content: char,
     fg: Option<Color>,
     bg: Option<Color>,
-    style: Option<Style>,
+    underline_color: Option<Color>,
+    style: Style,
+    inherit: Inherit,
+    theme: Option<Arc<Theme>>,
 }
 
 impl CellBuilder {
@@ -71,12 +206,45 @@ impl CellBuilder {
             content,
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
+            theme: None,
+        }
+    }
+
+    /// Creates a new `CellBuilder` that takes roles from a theme
+    pub(crate) fn with_theme(content: char, theme: Arc<Theme>) -> CellBuilder {
+        CellBuilder {
+            theme: Some(theme),
+            ..CellBuilder::new(content)
         }
     }
 
     impl_style_fns!(CellBuilder);
 
+    /// Sets the colors and styles from a role of the theme
+    ///
+    /// Builders made with `new` have no theme, so like unknown roles this leaves the colors and
+    /// styles unchanged, see `themed`
+    pub fn role(&mut self, role: &str) -> &mut CellBuilder {
+        let theme_style = self
+            .theme
+            .as_ref()
+            .and_then(|theme| theme.get(role))
+            .cloned();
+        if let Some(theme_style) = theme_style {
+            self.themed(&theme_style);
+        }
+        self
+    }
+
+    /// Sets which attributes are taken from the layer below
+    pub fn inherit(&mut self, inherit: Inherit) -> &mut CellBuilder {
+        self.inherit = inherit;
+        self
+    }
+
     /// Sets the character
     pub fn char(&mut self, content: char) -> &mut CellBuilder {
         self.content = content;
@@ -89,7 +257,10 @@ impl CellBuilder {
             content: self.content,
             fg: self.fg,
             bg: self.bg,
+            underline_color: self.underline_color,
             style: self.style,
+            inherit: self.inherit,
+            link: None,
             width: display_width(self.content) as u8,
         }
     }
@@ -100,64 +271,108 @@ impl CellBuilder {
             content: ch,
             fg: self.fg,
             bg: self.bg,
+            underline_color: self.underline_color,
             style: self.style,
-            width: display_width(self.content) as u8,
+            inherit: self.inherit,
+            link: None,
+            width: display_width(ch) as u8,
         }
     }
 }
 
 /// A builder to construct a set styled cells
 ///
-/// Create a `StyleCellBuilder` using [`char_builder`][::TermBuf::char_builder] and [`string_builder`][::TermBuf::string_builder]
+/// Create a `StyleCellBuilder` using [`char_builder`][::CellBuffer::char_builder] and [`string_builder`][::CellBuffer::string_builder]
 pub struct StyleCellBuilder<'a> {
-    buf: &'a mut Vec<Vec<TermCell>>,
+    buf: &'a mut Grid,
+    theme: &'a Theme,
+    links: &'a Mutex<LinkTable>,
     x: usize,
     y: usize,
     content: String,
     fg: Option<Color>,
     bg: Option<Color>,
-    style: Option<Style>,
+    underline_color: Option<Color>,
+    style: Style,
+    inherit: Inherit,
+    fg_gradient: Option<Gradient>,
+    link: Option<LinkId>,
 }
 
 impl<'a> StyleCellBuilder<'a> {
     /// Creates a new `StyleCellBuilder`
-    /// To be used by [`char_builder`][::TermBuf::char_builder] and [`string_builder`][::TermBuf::string_builder]
+    /// To be used by [`char_builder`][::CellBuffer::char_builder] and [`string_builder`][::CellBuffer::string_builder]
     pub(crate) fn new(
-        buf: &'a mut Vec<Vec<TermCell>>,
+        buf: &'a mut Grid,
+        theme: &'a Theme,
+        links: &'a Mutex<LinkTable>,
         x: usize,
         y: usize,
         content: String,
     ) -> StyleCellBuilder<'a> {
         StyleCellBuilder {
             buf,
+            theme,
+            links,
             content,
             x,
             y,
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
+            fg_gradient: None,
+            link: None,
         }
     }
 
     impl_style_fns!(StyleCellBuilder<'a>);
 
+    impl_role_fn!(StyleCellBuilder<'a>);
+
+    /// Colors the forground with a gradient running along the content, replacing `fg`
+    pub fn fg_gradient(&mut self, gradient: &Gradient) -> &mut StyleCellBuilder<'a> {
+        self.fg_gradient = Some(gradient.clone());
+        self
+    }
+
+    /// Makes the content a hyperlink to `target`, such as a url
+    pub fn link(&mut self, target: &str) -> &mut StyleCellBuilder<'a> {
+        self.link = Some(link::lock(self.links).intern(target));
+        self
+    }
+
+    /// Removes the hyperlink
+    pub fn no_link(&mut self) -> &mut StyleCellBuilder<'a> {
+        self.link = None;
+        self
+    }
+
     /// Writes all the new content to the terminal buffer
     pub fn draw(&mut self) {
+        let total_width: usize = self.content.chars().map(display_width).sum();
         let mut x = self.x;
         for ch in self.content.chars() {
             let width = display_width(ch);
+            let fg = match self.fg_gradient {
+                Some(ref gradient) if total_width > 1 => {
+                    Some(gradient.at((x - self.x) as f32 / (total_width - 1) as f32))
+                }
+                Some(ref gradient) => Some(gradient.at(0.0)),
+                None => self.fg,
+            };
             let new_cell = TermCell {
                 content: ch,
-                fg: self.fg,
+                fg,
                 bg: self.bg,
+                underline_color: self.underline_color,
                 style: self.style,
+                inherit: self.inherit,
+                link: self.link,
                 width: width as u8,
             };
-            if let Some(line) = self.buf.get_mut(self.y) {
-                if let Some(mut old_ch) = line.get_mut(x) {
-                    *old_ch = new_cell;
-                }
-            }
+            self.buf.set(x, self.y, new_cell);
             x += width;
         }
     }
@@ -170,36 +385,48 @@ enum LineOrientation {
 
 /// A builder to construct a styled line
 ///
-/// Create a `LineBuilder` using [`line_builder`][::TermBuf::line_builder]
+/// Create a `LineBuilder` using [`line_builder`][::CellBuffer::line_builder]
 pub struct LineBuilder<'a> {
-    buf: &'a mut Vec<Vec<TermCell>>,
+    buf: &'a mut Grid,
+    theme: &'a Theme,
     x: usize,
     y: usize,
     len: usize,
     orientation: Option<LineOrientation>,
+    border: BorderStyle,
+    merge: bool,
     fg: Option<Color>,
     bg: Option<Color>,
-    style: Option<Style>,
+    underline_color: Option<Color>,
+    style: Style,
+    inherit: Inherit,
 }
 
 impl<'a> LineBuilder<'a> {
     /// Creates a new `LineBuilder`
-    /// To be used by [`line_builder`][::TermBuf::line_builder]
+    /// To be used by [`line_builder`][::CellBuffer::line_builder]
     pub(crate) fn new(
-        buf: &'a mut Vec<Vec<TermCell>>,
+        buf: &'a mut Grid,
+        theme: &'a Theme,
         x: usize,
         y: usize,
         len: usize,
+        merge: bool,
     ) -> LineBuilder<'a> {
         LineBuilder {
             buf,
+            theme,
             x,
             y,
             len,
             orientation: None,
+            border: BorderStyle::Plain,
+            merge,
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
         }
     }
 
@@ -233,70 +460,177 @@ impl<'a> LineBuilder<'a> {
         self
     }
 
+    /// Sets the border style the line is drawn with
+    pub fn border(&mut self, border: BorderStyle) -> &mut LineBuilder<'a> {
+        self.border = border;
+        self
+    }
+
+    /// Sets whether the line merges with box drawing characters it crosses
+    ///
+    /// Defaults to [`CellBuffer::set_merge_junctions`][::CellBuffer::set_merge_junctions]
+    pub fn merge_junctions(&mut self, merge: bool) -> &mut LineBuilder<'a> {
+        self.merge = merge;
+        self
+    }
+
     impl_style_fns!(LineBuilder<'a>);
 
+    impl_role_fn!(LineBuilder<'a>);
+
     /// Writes the line to the terminal buffer
     pub fn draw(&mut self) {
+        let glyphs = self.border.glyphs();
         match self.orientation {
             None | Some(LineOrientation::Horizontal) => {
-                let mut builder = CellBuilder::new('─');
+                let mut builder = CellBuilder::new(glyphs.horizontal);
                 let horizontal = builder
                     .maybe_fg(self.fg)
                     .maybe_bg(self.bg)
-                    .maybe_styles(self.style);
+                    .maybe_underline_color(self.underline_color)
+                    .styles(self.style)
+                    .inherit(self.inherit);
+                let cell = horizontal.build();
+                let last = (self.len + self.x).saturating_sub(1);
                 for i in self.x..(self.len + self.x) {
-                    let cell = horizontal.build();
-                    set_cell(self.buf, cell, i, self.y);
+                    let open = [true, i != last, true, i != self.x];
+                    set_line_end_cell(self.buf, cell, i, self.y, self.merge, open);
                 }
             }
             Some(LineOrientation::Vertical) => {
+                let last = (self.len + self.y).saturating_sub(1);
                 for i in self.y..self.len + self.y {
-                    let mut builder = CellBuilder::new('│');
+                    let mut builder = CellBuilder::new(glyphs.vertical);
                     let vertical = builder
                         .maybe_fg(self.fg)
                         .maybe_bg(self.bg)
-                        .maybe_styles(self.style);
+                        .maybe_underline_color(self.underline_color)
+                        .styles(self.style)
+                        .inherit(self.inherit);
                     let cell = vertical.build();
-                    set_cell(self.buf, cell, self.x, i);
+                    let open = [i != self.y, true, i != last, true];
+                    set_line_end_cell(self.buf, cell, self.x, i, self.merge, open);
                 }
             }
         }
     }
 }
 
+/// A title or footer drawn in the border of a box
+#[derive(Default)]
+struct BoxLabel {
+    text: String,
+    align: Align,
+    style: Option<ThemeStyle>,
+}
+
+impl BoxLabel {
+    /// Writes the label into the border row `y`, between the corners at `left` and `right`
+    fn draw(
+        &self,
+        buf: &mut Grid,
+        border_cell: &CellBuilder,
+        padding: usize,
+        left: usize,
+        right: usize,
+        y: usize,
+    ) {
+        // Keep at least one border glyph next to each corner
+        let available = right.saturating_sub(left + 3);
+        let padding = if available > padding * 2 { padding } else { 0 };
+        let text = truncate(&self.text, available - padding * 2);
+        if text.is_empty() {
+            return;
+        }
+        let label = format!("{0:1$}{2}{0:1$}", "", padding, text);
+        let label_width: usize = label.chars().map(display_width).sum();
+        let start = match self.align {
+            Align::Left => left + 2,
+            Align::Center => left + 2 + (available - label_width) / 2,
+            Align::Right => right - 1 - label_width,
+        };
+
+        let mut builder = CellBuilder::new(' ');
+        let cell = match self.style {
+            Some(style) => builder.themed(&style),
+            None => builder
+                .maybe_fg(border_cell.fg)
+                .maybe_bg(border_cell.bg)
+                .styles(border_cell.style)
+                .inherit(border_cell.inherit),
+        };
+        let mut x = start;
+        for ch in label.chars() {
+            set_cell(buf, cell.build_with(ch), x, y);
+            x += display_width(ch);
+        }
+    }
+}
+
 /// A builder to construct a styled box
 ///
-/// Create a `BoxBuilder` using [`box_builder`][::TermBuf::box_builder]
+/// Create a `BoxBuilder` using [`box_builder`][::CellBuffer::box_builder]
 pub struct BoxBuilder<'a> {
-    buf: &'a mut Vec<Vec<TermCell>>,
+    buf: &'a mut Grid,
+    theme: &'a Theme,
     x: usize,
     y: usize,
     width: usize,
     height: usize,
+    border: BorderStyle,
+    sides: Sides,
+    merge: bool,
+    fill_char: Option<char>,
+    fill_bg: Option<Color>,
+    clear: bool,
+    shadow: bool,
+    /// The area the shadow is cut to, if any
+    shadow_area: Option<Rect>,
+    title: BoxLabel,
+    footer: BoxLabel,
+    label_padding: usize,
     fg: Option<Color>,
     bg: Option<Color>,
-    style: Option<Style>,
+    underline_color: Option<Color>,
+    style: Style,
+    inherit: Inherit,
 }
 
 impl<'a> BoxBuilder<'a> {
     /// Creates a new `BoxBuilder`
-    /// To be used by [`box_builder`][::TermBuf::box_builder]
+    /// To be used by [`box_builder`][::CellBuffer::box_builder]
     pub(crate) fn new(
-        buf: &'a mut Vec<Vec<TermCell>>,
+        buf: &'a mut Grid,
+        theme: &'a Theme,
         x: usize,
         y: usize,
         width: usize,
         height: usize,
+        merge: bool,
     ) -> BoxBuilder<'a> {
         BoxBuilder {
             buf,
+            theme,
             x,
             y,
             width,
             height,
+            border: BorderStyle::Plain,
+            sides: Sides::all(),
+            merge,
+            fill_char: None,
+            fill_bg: None,
+            clear: false,
+            shadow: false,
+            shadow_area: None,
+            title: BoxLabel::default(),
+            footer: BoxLabel::default(),
+            label_padding: 1,
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
         }
     }
 
@@ -324,35 +658,240 @@ impl<'a> BoxBuilder<'a> {
         self
     }
 
+    /// Sets the border style
+    pub fn border(&mut self, border: BorderStyle) -> &mut BoxBuilder<'a> {
+        self.border = border;
+        self
+    }
+
+    /// Sets which sides have a border, all by default
+    pub fn sides(&mut self, sides: Sides) -> &mut BoxBuilder<'a> {
+        self.sides = sides;
+        self
+    }
+
+    /// Sets whether the border merges with box drawing characters it is drawn over
+    ///
+    /// Defaults to [`CellBuffer::set_merge_junctions`][::CellBuffer::set_merge_junctions]
+    pub fn merge_junctions(&mut self, merge: bool) -> &mut BoxBuilder<'a> {
+        self.merge = merge;
+        self
+    }
+
+    /// Fills the interior with a character, drawn with the box's colors and styles
+    pub fn fill_char(&mut self, ch: char) -> &mut BoxBuilder<'a> {
+        self.fill_char = Some(ch);
+        self
+    }
+
+    /// Fills the interior with a background color
+    ///
+    /// Combine with `fill_char` to fill with a character on this background
+    pub fn fill_bg(&mut self, color: Color) -> &mut BoxBuilder<'a> {
+        self.fill_bg = Some(color);
+        self
+    }
+
+    /// Empties the interior before drawing
+    pub fn clear_interior(&mut self) -> &mut BoxBuilder<'a> {
+        self.clear = true;
+        self
+    }
+
+    /// Sets whether the box casts a shadow on the cells to its right and below it
+    ///
+    /// Shadowed cells keep their content but are drawn faint, with a darker background
+    pub fn shadow(&mut self, shadow: bool) -> &mut BoxBuilder<'a> {
+        self.shadow = shadow;
+        self
+    }
+
+    /// Keeps the shadow inside an area, used by popups so they stay in theirs
+    pub(crate) fn shadow_area(&mut self, area: Rect) -> &mut BoxBuilder<'a> {
+        self.shadow_area = Some(area);
+        self
+    }
+
+    /// Sets the title, drawn in the top border
+    ///
+    /// Titles wider than the box are truncated with an ellipsis
+    pub fn title(&mut self, title: &str) -> &mut BoxBuilder<'a> {
+        self.title.text = title.to_owned();
+        self
+    }
+
+    /// Sets the alignment of the title, left by default
+    pub fn title_align(&mut self, align: Align) -> &mut BoxBuilder<'a> {
+        self.title.align = align;
+        self
+    }
+
+    /// Sets the colors and styles of the title, which otherwise uses those of the border
+    pub fn title_style(&mut self, style: ThemeStyle) -> &mut BoxBuilder<'a> {
+        self.title.style = Some(style);
+        self
+    }
+
+    /// Sets the colors and styles of the title from a role of the current theme
+    pub fn title_role(&mut self, role: &str) -> &mut BoxBuilder<'a> {
+        if let Some(style) = self.theme.get(role).cloned() {
+            self.title_style(style);
+        }
+        self
+    }
+
+    /// Sets the footer, drawn in the bottom border
+    ///
+    /// Footers wider than the box are truncated with an ellipsis
+    pub fn footer(&mut self, footer: &str) -> &mut BoxBuilder<'a> {
+        self.footer.text = footer.to_owned();
+        self
+    }
+
+    /// Sets the alignment of the footer, left by default
+    pub fn footer_align(&mut self, align: Align) -> &mut BoxBuilder<'a> {
+        self.footer.align = align;
+        self
+    }
+
+    /// Sets the colors and styles of the footer, which otherwise uses those of the border
+    pub fn footer_style(&mut self, style: ThemeStyle) -> &mut BoxBuilder<'a> {
+        self.footer.style = Some(style);
+        self
+    }
+
+    /// Sets the colors and styles of the footer from a role of the current theme
+    pub fn footer_role(&mut self, role: &str) -> &mut BoxBuilder<'a> {
+        if let Some(style) = self.theme.get(role).cloned() {
+            self.footer_style(style);
+        }
+        self
+    }
+
+    /// Sets the number of spaces on either side of the title and footer, one by default
+    pub fn label_padding(&mut self, padding: usize) -> &mut BoxBuilder<'a> {
+        self.label_padding = padding;
+        self
+    }
+
     impl_style_fns!(BoxBuilder<'a>);
 
+    impl_role_fn!(BoxBuilder<'a>);
+
     /// Writes the line to the terminal buffer
     pub fn draw(&mut self) {
         let mut builder = CellBuilder::new(' ');
         let cell = builder
             .maybe_fg(self.fg)
             .maybe_bg(self.bg)
-            .maybe_styles(self.style);
-        let width = self.width + 1;
-        let height = self.height + 1;
-        set_cell(self.buf, cell.build_with('┌'), self.x, self.y);
-        set_cell(self.buf, cell.build_with('┐'), self.x + width, self.y);
-        set_cell(self.buf, cell.build_with('└'), self.x, self.y + height);
-        set_cell(
-            self.buf,
-            cell.build_with('┘'),
-            self.x + width,
-            self.y + height,
-        );
-
-        for i in (self.x + 1)..(width + self.x) {
-            set_cell(self.buf, cell.build_with('─'), i, self.y);
-            set_cell(self.buf, cell.build_with('─'), i, self.y + height);
-        }
-
-        for i in self.y + 1..height + self.y {
-            set_cell(self.buf, cell.build_with('│'), self.x, i);
-            set_cell(self.buf, cell.build_with('│'), self.x + width, i);
+            .maybe_underline_color(self.underline_color)
+            .styles(self.style)
+            .inherit(self.inherit);
+        let glyphs = self.border.glyphs();
+        let right = self.x + self.width + 1;
+        let bottom = self.y + self.height + 1;
+
+        if self.clear || self.fill_char.is_some() || self.fill_bg.is_some() {
+            let fill = match self.fill_char {
+                Some(ch) => cell.build_with(ch),
+                None => TermCell::empty(),
+            };
+            let fill = TermCell {
+                bg: self.fill_bg.or(fill.bg),
+                ..fill
+            };
+            // Wide characters that would cover the right border are replaced with spaces
+            for y in (self.y + 1)..bottom {
+                self.buf.fill_span(y, self.x + 1, right, fill);
+            }
+        }
+        let top_side = self.sides.contains(Sides::Top);
+        let bottom_side = self.sides.contains(Sides::Bottom);
+        let left_side = self.sides.contains(Sides::Left);
+        let right_side = self.sides.contains(Sides::Right);
+
+        // A corner only joins two sides if both are drawn, otherwise it continues the one side
+        let corner = |horizontal: bool, vertical: bool, ch: char| match (horizontal, vertical) {
+            (true, true) => Some(ch),
+            (true, false) => Some(glyphs.horizontal),
+            (false, true) => Some(glyphs.vertical),
+            (false, false) => None,
+        };
+        let corners = [
+            (self.x, self.y, corner(top_side, left_side, glyphs.top_left)),
+            (
+                right,
+                self.y,
+                corner(top_side, right_side, glyphs.top_right),
+            ),
+            (
+                self.x,
+                bottom,
+                corner(bottom_side, left_side, glyphs.bottom_left),
+            ),
+            (
+                right,
+                bottom,
+                corner(bottom_side, right_side, glyphs.bottom_right),
+            ),
+        ];
+        if self.shadow {
+            let mut patch = StylePatch::new();
+            patch.add(Style::Faint);
+            let (left, top) = (self.x, self.y);
+            let buf = &mut *self.buf;
+            let area = self.shadow_area;
+            let mut dim = |x: usize, y: usize| {
+                if area.is_some_and(|area| !area.contains(x, y)) {
+                    return;
+                }
+                buf.update(x, y, |cell| {
+                    patch.apply(cell);
+                    cell.bg = cell.bg.map(|bg| bg.darken(0.3));
+                });
+            };
+            for y in (top + 1)..=(bottom + 1) {
+                dim(right + 1, y);
+            }
+            for x in (left + 1)..=right {
+                dim(x, bottom + 1);
+            }
+        }
+
+        for &(x, y, ch) in &corners {
+            if let Some(ch) = ch {
+                set_border_cell(self.buf, cell.build_with(ch), x, y, self.merge);
+            }
+        }
+
+        let horizontal = cell.build_with(glyphs.horizontal);
+        for i in (self.x + 1)..right {
+            if top_side {
+                set_border_cell(self.buf, horizontal, i, self.y, self.merge);
+            }
+            if bottom_side {
+                set_border_cell(self.buf, horizontal, i, bottom, self.merge);
+            }
+        }
+
+        let vertical = cell.build_with(glyphs.vertical);
+        for i in (self.y + 1)..bottom {
+            if left_side {
+                set_border_cell(self.buf, vertical, self.x, i, self.merge);
+            }
+            if right_side {
+                set_border_cell(self.buf, vertical, right, i, self.merge);
+            }
+        }
+
+        // Labels sit in their border, so are left out with it
+        if top_side {
+            self.title
+                .draw(self.buf, cell, self.label_padding, self.x, right, self.y);
+        }
+        if bottom_side {
+            self.footer
+                .draw(self.buf, cell, self.label_padding, self.x, right, bottom);
         }
     }
 }
@@ -1,3 +1,6 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 use termion::color;
 
 macro_rules! impl_color {
@@ -25,7 +28,7 @@ macro_rules! impl_color {
 /// Represents a forground or background color for a cell
 ///
 /// See the [termion docs](::termion::color) for details
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Color {
     AnsiValue(u8),
     Rgb(u8, u8, u8),
@@ -67,3 +70,435 @@ impl_color! {
     LightWhite,
     LightYellow
 }
+
+/// The xterm default palette for the 16 basic colors
+const BASIC_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The channel levels of the 6x6x6 color cube in the 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Color {
+    /// Returns the index of the color in the 256 color palette, `None` for `Rgb`
+    pub fn ansi_index(self) -> Option<u8> {
+        use self::Color::*;
+        Some(match self {
+            AnsiValue(v) => v,
+            Rgb(..) => return None,
+            Black => 0,
+            Red => 1,
+            Green => 2,
+            Yellow => 3,
+            Blue => 4,
+            Magenta => 5,
+            Cyan => 6,
+            White => 7,
+            LightBlack => 8,
+            LightRed => 9,
+            LightGreen => 10,
+            LightYellow => 11,
+            LightBlue => 12,
+            LightMagenta => 13,
+            LightCyan => 14,
+            LightWhite => 15,
+        })
+    }
+
+    /// Converts the color to rgb components
+    ///
+    /// Named and `AnsiValue` colors use the xterm default palette, the actual color shown
+    /// depends on the terminal's configuration
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        let index = match self {
+            Color::Rgb(r, g, b) => return (r, g, b),
+            other => other.ansi_index().unwrap_or(0),
+        };
+        match index {
+            0..=15 => BASIC_PALETTE[index as usize],
+            16..=231 => {
+                let i = index - 16;
+                (
+                    CUBE_LEVELS[(i / 36) as usize],
+                    CUBE_LEVELS[(i / 6 % 6) as usize],
+                    CUBE_LEVELS[(i % 6) as usize],
+                )
+            }
+            _ => {
+                let level = 8 + (index - 232) * 10;
+                (level, level, level)
+            }
+        }
+    }
+
+    /// Returns the closest color in the 256 color palette, other colors are returned unchanged
+    pub fn to_ansi256(self) -> Color {
+        let (r, g, b) = match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            other => return other,
+        };
+        let distance = |(r2, g2, b2): (u8, u8, u8)| {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+            d(r, r2) + d(g, g2) + d(b, b2)
+        };
+        let level = |v: u8| {
+            CUBE_LEVELS
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, l)| (i32::from(**l) - i32::from(v)).abs())
+                .map(|(i, _)| i as u8)
+                .unwrap_or(0)
+        };
+        let cube = Color::AnsiValue(16 + 36 * level(r) + 6 * level(g) + level(b));
+        let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+        let gray = Color::AnsiValue(232 + (average.saturating_sub(3) / 10).min(23) as u8);
+        if distance(gray.to_rgb()) < distance(cube.to_rgb()) {
+            gray
+        } else {
+            cube
+        }
+    }
+
+    /// Converts the color to hue (0-360), saturation (0-1), and lightness (0-1)
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (r, g, b) = self.to_rgb();
+        let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+        if d == 0.0 {
+            return (0.0, 0.0, l);
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / d).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+        (h, s, l)
+    }
+
+    /// Creates an rgb color from hue (0-360), saturation (0-1), and lightness (0-1)
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Color {
+        let s = s.clamp(0.0, 1.0);
+        let l = l.clamp(0.0, 1.0);
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = l - c / 2.0;
+        let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+        Color::Rgb(channel(r), channel(g), channel(b))
+    }
+
+    /// Mixes two colors, `t` of 0 gives `a` and `t` of 1 gives `b`
+    pub fn blend(a: Color, b: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let (ar, ag, ab) = a.to_rgb();
+        let (br, bg, bb) = b.to_rgb();
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::Rgb(mix(ar, br), mix(ag, bg), mix(ab, bb))
+    }
+
+    /// Increases the lightness of the color by `amount` (0-1)
+    pub fn lighten(self, amount: f32) -> Color {
+        let (h, s, l) = self.to_hsl();
+        Color::from_hsl(h, s, l + amount)
+    }
+
+    /// Decreases the lightness of the color by `amount` (0-1)
+    pub fn darken(self, amount: f32) -> Color {
+        let (h, s, l) = self.to_hsl();
+        Color::from_hsl(h, s, l - amount)
+    }
+
+    /// Returns the relative luminance of the color, as defined by WCAG
+    pub fn luminance(self) -> f32 {
+        let (r, g, b) = self.to_rgb();
+        let linear = |v: u8| {
+            let v = v as f32 / 255.0;
+            if v <= 0.03928 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+    }
+
+    /// Returns black or white, whichever contrasts more with the background `bg`
+    pub fn readable_on(bg: Color) -> Color {
+        let lum = bg.luminance();
+        // Contrast ratios against black and white are equal at this luminance
+        if lum > 0.179 {
+            Color::Black
+        } else {
+            Color::White
+        }
+    }
+}
+
+/// Sets the underline color when displayed, like termion's `Fg` and `Bg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnderlineColor(pub Color);
+
+impl fmt::Display for UnderlineColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Color::Rgb(r, g, b) => write!(f, "\x1b[58;2;{};{};{}m", r, g, b),
+            other => write!(f, "\x1b[58;5;{}m", other.ansi_index().unwrap_or(0)),
+        }
+    }
+}
+
+/// Resets the underline color to the forground color when displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnderlineColorReset;
+
+impl fmt::Display for UnderlineColorReset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\x1b[59m")
+    }
+}
+
+/// The names of the named colors, as used by `FromStr` and `Display`
+const NAMES: &[(&str, Color)] = &[
+    ("black", Color::Black),
+    ("blue", Color::Blue),
+    ("cyan", Color::Cyan),
+    ("green", Color::Green),
+    ("magenta", Color::Magenta),
+    ("red", Color::Red),
+    ("white", Color::White),
+    ("yellow", Color::Yellow),
+    ("light-black", Color::LightBlack),
+    ("light-blue", Color::LightBlue),
+    ("light-cyan", Color::LightCyan),
+    ("light-green", Color::LightGreen),
+    ("light-magenta", Color::LightMagenta),
+    ("light-red", Color::LightRed),
+    ("light-white", Color::LightWhite),
+    ("light-yellow", Color::LightYellow),
+];
+
+/// An error returned when parsing a `Color` fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError {
+    input: String,
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid color: `{}`", self.input)
+    }
+}
+
+impl Error for ParseColorError {}
+
+/// Parses the inside of a function-like color, such as `rgb(1, 2, 3)`
+fn parse_call<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = s.get(..name.len());
+    if s.len() > name.len() && prefix.is_some_and(|p| p.eq_ignore_ascii_case(name)) {
+        let rest = s[name.len()..].trim_start();
+        if rest.starts_with('(') && rest.ends_with(')') {
+            return Some(&rest[1..rest.len() - 1]);
+        }
+    }
+    None
+}
+
+/// Parses a number from 0 to 255 written only with digits, without a sign
+fn parse_u8(s: &str) -> Option<u8> {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..=i], 16).ok();
+    let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match hex.len() {
+        3 => Some(Color::Rgb(digit(0)? * 17, digit(1)? * 17, digit(2)? * 17)),
+        6 => Some(Color::Rgb(pair(0)?, pair(2)?, pair(4)?)),
+        _ => None,
+    }
+}
+
+fn parse_rgb(args: &str) -> Option<Color> {
+    let mut parts = args.split(',').map(|part| parse_u8(part.trim()));
+    let color = Color::Rgb(parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        None
+    } else {
+        Some(color)
+    }
+}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    /// Parses a color from a string
+    ///
+    /// Accepts named colors (`red`, `light-blue`, `light_blue`, `lightblue`), `#rrggbb`, `#rgb`,
+    /// `rgb(r, g, b)`, and 256 color values as either `ansi(123)` or `123`
+    fn from_str(s: &str) -> Result<Color, ParseColorError> {
+        let s = s.trim();
+        let parsed = if let Some(hex) = s.strip_prefix('#') {
+            parse_hex(hex)
+        } else if let Some(args) = parse_call(s, "rgb") {
+            parse_rgb(args)
+        } else if let Some(args) = parse_call(s, "ansi") {
+            parse_u8(args.trim()).map(Color::AnsiValue)
+        } else if let Some(value) = parse_u8(s) {
+            Some(Color::AnsiValue(value))
+        } else {
+            let name = s.to_ascii_lowercase().replace('_', "-");
+            NAMES
+                .iter()
+                .find(|(n, _)| *n == name || n.replace('-', "") == name)
+                .map(|(_, color)| *color)
+        };
+        parsed.ok_or_else(|| ParseColorError {
+            input: s.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Color {
+    /// Formats the color so that it can be parsed back with `FromStr`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Color::AnsiValue(v) => write!(f, "ansi({})", v),
+            Color::Rgb(r, g, b) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+            named => {
+                let (name, _) = NAMES
+                    .iter()
+                    .find(|(_, color)| color == named)
+                    .expect("every named color has a name");
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Color;
+
+    #[test]
+    fn parse_named() {
+        assert_eq!("red".parse(), Ok(Color::Red));
+        assert_eq!("light-blue".parse(), Ok(Color::LightBlue));
+        assert_eq!("Light_Blue".parse(), Ok(Color::LightBlue));
+        assert_eq!("lightblue".parse(), Ok(Color::LightBlue));
+        assert!("blurple".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!("#ff8000".parse(), Ok(Color::Rgb(255, 128, 0)));
+        assert_eq!("#f80".parse(), Ok(Color::Rgb(255, 136, 0)));
+        assert_eq!("rgb(1, 2,3)".parse(), Ok(Color::Rgb(1, 2, 3)));
+        assert_eq!("ansi(123)".parse(), Ok(Color::AnsiValue(123)));
+        assert_eq!("123".parse(), Ok(Color::AnsiValue(123)));
+        assert!("#ff80".parse::<Color>().is_err());
+        assert!("rgb(1, 2, 300)".parse::<Color>().is_err());
+        assert!("rgb(1, 2, 3, 4)".parse::<Color>().is_err());
+        assert!("256".parse::<Color>().is_err());
+        assert!("+5".parse::<Color>().is_err());
+        assert!("ansi(+5)".parse::<Color>().is_err());
+        assert!("rgb(+1, 2, 3)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn parse_non_ascii() {
+        assert!("ab€".parse::<Color>().is_err());
+        assert!("abc€x".parse::<Color>().is_err());
+        assert!("ans€(1)".parse::<Color>().is_err());
+        assert!("€".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let colors = [
+            Color::Red,
+            Color::LightMagenta,
+            Color::Rgb(1, 20, 255),
+            Color::AnsiValue(42),
+        ];
+        for color in &colors {
+            assert_eq!(color.to_string().parse(), Ok(*color));
+        }
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(Color::LightBlue.to_rgb(), (92, 92, 255));
+        assert_eq!(Color::AnsiValue(1).to_rgb(), Color::Red.to_rgb());
+        assert_eq!(Color::AnsiValue(196).to_rgb(), (255, 0, 0));
+        assert_eq!(Color::AnsiValue(232).to_rgb(), (8, 8, 8));
+        assert_eq!(Color::AnsiValue(255).to_rgb(), (238, 238, 238));
+        assert_eq!(Color::Rgb(250, 5, 0).to_ansi256(), Color::AnsiValue(196));
+        assert_eq!(
+            Color::Rgb(100, 100, 100).to_ansi256(),
+            Color::AnsiValue(241)
+        );
+        assert_eq!(Color::Red.to_ansi256(), Color::Red);
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        let colors = [
+            Color::Rgb(255, 0, 0),
+            Color::Rgb(12, 200, 99),
+            Color::Rgb(40, 40, 40),
+            Color::Rgb(90, 10, 240),
+        ];
+        for color in &colors {
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), *color);
+        }
+    }
+
+    #[test]
+    fn manipulation() {
+        let black = Color::Rgb(0, 0, 0);
+        let white = Color::Rgb(255, 255, 255);
+        assert_eq!(Color::blend(black, white, 0.5), Color::Rgb(128, 128, 128));
+        assert_eq!(Color::blend(black, white, 2.0), white);
+        assert_eq!(black.lighten(1.0), white);
+        assert_eq!(Color::Rgb(255, 0, 0).darken(0.25), Color::Rgb(128, 0, 0));
+        assert_eq!(Color::readable_on(Color::Yellow), Color::Black);
+        assert_eq!(Color::readable_on(Color::Blue), Color::White);
+    }
+}
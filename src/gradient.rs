@@ -0,0 +1,118 @@
+use Color;
+
+fn rgb(color: Color) -> Color {
+    let (r, g, b) = color.to_rgb();
+    Color::Rgb(r, g, b)
+}
+
+/// The direction a gradient runs in when filling an area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientDirection {
+    /// From the left edge to the right edge
+    Horizontal,
+    /// From the top edge to the bottom edge
+    Vertical,
+    /// From the top left corner to the bottom right corner
+    Diagonal,
+}
+
+/// A linear color gradient with any number of stops
+///
+/// Colors are blended in rgb, so the result is always a [`Color::Rgb`], which is downsampled
+/// when flushing if the terminal lacks truecolor support
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, Color)>,
+    direction: GradientDirection,
+}
+
+impl Gradient {
+    /// Creates a horizontal gradient between two colors
+    pub fn new(from: Color, to: Color) -> Gradient {
+        Gradient {
+            stops: vec![(0.0, from), (1.0, to)],
+            direction: GradientDirection::Horizontal,
+        }
+    }
+
+    /// Creates a horizontal gradient from stops, each a position (0-1) and a color
+    ///
+    /// Colors before the first stop and after the last stop are the same as those stops
+    pub fn with_stops(stops: &[(f32, Color)]) -> Gradient {
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+        Gradient {
+            stops,
+            direction: GradientDirection::Horizontal,
+        }
+    }
+
+    /// Creates a gradient with evenly spaced stops
+    pub fn evenly(colors: &[Color]) -> Gradient {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        let stops: Vec<_> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (i as f32 / last, *color))
+            .collect();
+        Gradient::with_stops(&stops)
+    }
+
+    /// Sets the direction
+    pub fn direction(&mut self, direction: GradientDirection) -> &mut Gradient {
+        self.direction = direction;
+        self
+    }
+
+    /// Sets the direction to horizontal
+    pub fn horizontal(&mut self) -> &mut Gradient {
+        self.direction(GradientDirection::Horizontal)
+    }
+
+    /// Sets the direction to vertical
+    pub fn vertical(&mut self) -> &mut Gradient {
+        self.direction(GradientDirection::Vertical)
+    }
+
+    /// Sets the direction to diagonal
+    pub fn diagonal(&mut self) -> &mut Gradient {
+        self.direction(GradientDirection::Diagonal)
+    }
+
+    /// Returns the color at a position (0-1) along the gradient
+    pub fn at(&self, t: f32) -> Color {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::Rgb(0, 0, 0),
+        };
+        if t <= first.0 {
+            return rgb(first.1);
+        }
+        for pair in self.stops.windows(2) {
+            let ((start, from), (end, to)) = (pair[0], pair[1]);
+            if t <= end {
+                let span = end - start;
+                let local = if span > 0.0 { (t - start) / span } else { 1.0 };
+                return Color::blend(from, to, local);
+            }
+        }
+        rgb(last.1)
+    }
+
+    /// Returns the color of the cell at (`x`, `y`) in an area of `width` by `height` cells
+    pub fn at_cell(&self, x: usize, y: usize, width: usize, height: usize) -> Color {
+        let fraction = |pos: usize, len: usize| {
+            if len > 1 {
+                pos as f32 / (len - 1) as f32
+            } else {
+                0.0
+            }
+        };
+        let t = match self.direction {
+            GradientDirection::Horizontal => fraction(x, width),
+            GradientDirection::Vertical => fraction(y, height),
+            GradientDirection::Diagonal => (fraction(x, width) + fraction(y, height)) / 2.0,
+        };
+        self.at(t)
+    }
+}
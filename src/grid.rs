@@ -0,0 +1,296 @@
+use std::ops::{Index, IndexMut, Range};
+use std::slice::Chunks;
+
+use packed::PackedCell;
+use {Rect, TermCell};
+
+/// Returns a space with the colors and styles of a wide character, for what is left of one cut
+/// in half
+pub(crate) fn blank_half(wide: PackedCell) -> PackedCell {
+    let mut half = wide.unpack();
+    half.content = ' ';
+    half.width = 1;
+    PackedCell::new(&half)
+}
+
+/// Makes column `x` of a row the start of a cell, so drawing from there or up to there doesn't
+/// leave half a wide character
+///
+/// A wide character covering `x` has both halves replaced with spaces. Every edge of a drawing
+/// goes through this, so wide characters look the same however they are cut
+pub(crate) fn split_wide(line: &mut [PackedCell], x: usize) {
+    if x > 0 && x < line.len() && line[x - 1].width() > 1 {
+        let half = blank_half(line[x - 1]);
+        line[x - 1] = half;
+        line[x] = half;
+    }
+}
+
+/// Cells stored packed, row after row in a single allocation
+///
+/// Indexing with a row number gives that row as a slice
+///
+/// Rows are marked dirty when their cells are changed, until [`clean`][Grid::clean] is called
+#[derive(Debug, Default)]
+pub(crate) struct Grid {
+    cells: Vec<PackedCell>,
+    width: usize,
+    height: usize,
+    /// Whether each row was changed since the grid was last cleaned
+    dirty: Vec<bool>,
+}
+
+impl Grid {
+    /// Creates a grid with every cell set to `cell`, and every row dirty
+    pub(crate) fn new(width: usize, height: usize, cell: TermCell) -> Grid {
+        Grid {
+            cells: vec![PackedCell::new(&cell); width * height],
+            width,
+            height,
+            dirty: vec![true; height],
+        }
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Changes the size, setting every cell to `cell` and keeping the allocation when possible
+    pub(crate) fn resize(&mut self, width: usize, height: usize, cell: TermCell) {
+        self.cells.clear();
+        self.cells.resize(width * height, PackedCell::new(&cell));
+        self.width = width;
+        self.height = height;
+        self.dirty.clear();
+        self.dirty.resize(height, true);
+    }
+
+    /// Sets every cell to `cell`
+    pub(crate) fn fill(&mut self, cell: TermCell) {
+        let cell = PackedCell::new(&cell);
+        for old_cell in &mut self.cells {
+            *old_cell = cell;
+        }
+        self.mark_dirty(0..self.height);
+    }
+
+    /// Sets the cells of a row from `start` up to `end` to `cell`, ignoring those outside the grid
+    ///
+    /// Wide characters are repeated every other column, and the columns they cover are set to
+    /// spaces. A space is also used where a wide character would not fit before `end`, and wide
+    /// characters cut in half by the span are replaced with spaces
+    pub(crate) fn fill_span(&mut self, y: usize, start: usize, end: usize, cell: TermCell) {
+        let step = (cell.width as usize).max(1);
+        let space = PackedCell::new(&TermCell {
+            content: ' ',
+            width: 1,
+            ..cell
+        });
+        let cell = PackedCell::new(&cell);
+        if let Some(line) = self.row_mut(y) {
+            let end = end.min(line.len());
+            if start >= end {
+                return;
+            }
+            split_wide(line, start);
+            split_wide(line, end);
+            let mut x = start;
+            while x < end {
+                if x + step <= end {
+                    line[x] = cell;
+                    for covered in &mut line[x + 1..x + step] {
+                        *covered = space;
+                    }
+                    x += step;
+                } else {
+                    line[x] = space;
+                    x += 1;
+                }
+            }
+        }
+    }
+
+    /// Makes column `x` of row `y` the start of a cell, see [`split_wide`]
+    pub(crate) fn split_wide_at(&mut self, y: usize, x: usize) {
+        if let Some(line) = self.row_mut(y) {
+            split_wide(line, x);
+        }
+    }
+
+    /// Sets every cell in an area to `cell`, the area must be in the grid
+    pub(crate) fn fill_rect(&mut self, rect: Rect, cell: TermCell) {
+        let cell = PackedCell::new(&cell);
+        for y in rect.y..rect.y + rect.height {
+            for old_cell in &mut self[y][rect.x..rect.x + rect.width] {
+                *old_cell = cell;
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, x: usize, y: usize) -> Option<TermCell> {
+        if x < self.width && y < self.height {
+            Some(self.cells[y * self.width + x].unpack())
+        } else {
+            None
+        }
+    }
+
+    /// Replaces a cell, if it is in the grid
+    pub(crate) fn set(&mut self, x: usize, y: usize, cell: TermCell) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = PackedCell::new(&cell);
+            self.dirty[y] = true;
+        }
+    }
+
+    /// Changes a cell in place, if it is in the grid
+    pub(crate) fn update<F: FnOnce(&mut TermCell)>(&mut self, x: usize, y: usize, f: F) {
+        if x < self.width && y < self.height {
+            let packed = &mut self.cells[y * self.width + x];
+            let mut cell = packed.unpack();
+            f(&mut cell);
+            *packed = PackedCell::new(&cell);
+            self.dirty[y] = true;
+        }
+    }
+
+    /// Moves the cells in an area up by `n` rows, or down if `n` is negative, setting the rows
+    /// left behind to `cell`
+    pub(crate) fn scroll(&mut self, rect: Rect, n: isize, cell: TermCell) {
+        let (left, right) = (
+            rect.x.min(self.width),
+            (rect.x + rect.width).min(self.width),
+        );
+        let (top, bottom) = (
+            rect.y.min(self.height),
+            (rect.y + rect.height).min(self.height),
+        );
+        let shift = n.unsigned_abs().min(bottom - top);
+        let width = self.width;
+        let span = |y: usize| y * width + left..y * width + right;
+        let blank = if n > 0 {
+            for y in top..bottom - shift {
+                self.cells.copy_within(span(y + shift), y * width + left);
+            }
+            bottom - shift..bottom
+        } else {
+            for y in (top + shift..bottom).rev() {
+                self.cells.copy_within(span(y - shift), y * width + left);
+            }
+            top..top + shift
+        };
+        let cell = PackedCell::new(&cell);
+        for y in blank {
+            for old_cell in &mut self.cells[span(y)] {
+                *old_cell = cell;
+            }
+        }
+        self.mark_dirty(top..bottom);
+    }
+
+    /// Returns whether any row was changed since the grid was last cleaned
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty.contains(&true)
+    }
+
+    pub(crate) fn is_row_dirty(&self, y: usize) -> bool {
+        self.dirty.get(y).cloned().unwrap_or(false)
+    }
+
+    /// Marks rows as changed, ignoring those outside the grid
+    pub(crate) fn mark_dirty(&mut self, rows: Range<usize>) {
+        let end = rows.end.min(self.height);
+        for dirty in &mut self.dirty[rows.start.min(end)..end] {
+            *dirty = true;
+        }
+    }
+
+    /// Marks every row as unchanged
+    pub(crate) fn clean(&mut self) {
+        for dirty in &mut self.dirty {
+            *dirty = false;
+        }
+    }
+
+    pub(crate) fn row(&self, y: usize) -> Option<&[PackedCell]> {
+        if y < self.height {
+            Some(&self[y])
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn row_mut(&mut self, y: usize) -> Option<&mut [PackedCell]> {
+        if y < self.height {
+            Some(&mut self[y])
+        } else {
+            None
+        }
+    }
+
+    /// Counts the cells of a row that differ from `line`, all of them if the row is missing or
+    /// has another width
+    pub(crate) fn changed_cells(&self, y: usize, line: &[PackedCell]) -> usize {
+        match self.row(y) {
+            Some(old_line) if old_line.len() == line.len() => old_line
+                .iter()
+                .zip(line)
+                .filter(|(old, new)| old != new)
+                .count(),
+            _ => line.len(),
+        }
+    }
+
+    /// Iterates over the rows, none if the grid has no width
+    pub(crate) fn rows(&self) -> Chunks<'_, PackedCell> {
+        self.cells.chunks(self.width.max(1))
+    }
+}
+
+impl Clone for Grid {
+    fn clone(&self) -> Grid {
+        Grid {
+            cells: self.cells.clone(),
+            width: self.width,
+            height: self.height,
+            dirty: self.dirty.clone(),
+        }
+    }
+
+    /// Copies another grid, reusing the allocation when it is large enough
+    fn clone_from(&mut self, source: &Grid) {
+        self.cells.clone_from(&source.cells);
+        self.width = source.width;
+        self.height = source.height;
+        self.dirty.clone_from(&source.dirty);
+    }
+}
+
+/// Grids are equal when their cells are, whichever rows are dirty
+impl PartialEq for Grid {
+    fn eq(&self, other: &Grid) -> bool {
+        self.width == other.width && self.height == other.height && self.cells == other.cells
+    }
+}
+
+impl Index<usize> for Grid {
+    type Output = [PackedCell];
+
+    fn index(&self, y: usize) -> &[PackedCell] {
+        assert!(y < self.height, "row {} out of range", y);
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+}
+
+impl IndexMut<usize> for Grid {
+    /// Marks the row dirty, whether or not it is changed
+    fn index_mut(&mut self, y: usize) -> &mut [PackedCell] {
+        assert!(y < self.height, "row {} out of range", y);
+        self.dirty[y] = true;
+        &mut self.cells[y * self.width..(y + 1) * self.width]
+    }
+}
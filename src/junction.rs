@@ -0,0 +1,207 @@
+/// No line leaves the cell in this direction
+const NONE: u8 = 0;
+/// A light line leaves the cell in this direction
+const LIGHT: u8 = 1;
+/// A heavy line leaves the cell in this direction
+const HEAVY: u8 = 2;
+/// A double line leaves the cell in this direction
+const DOUBLE: u8 = 3;
+
+/// The weight of the lines leaving each box drawing character, as up, right, down, left
+///
+/// When several characters have the same arms the first one is used for merged junctions
+const ARMS: &[(char, [u8; 4])] = &[
+    ('─', [NONE, LIGHT, NONE, LIGHT]),
+    ('━', [NONE, HEAVY, NONE, HEAVY]),
+    ('│', [LIGHT, NONE, LIGHT, NONE]),
+    ('┃', [HEAVY, NONE, HEAVY, NONE]),
+    ('┌', [NONE, LIGHT, LIGHT, NONE]),
+    ('┍', [NONE, HEAVY, LIGHT, NONE]),
+    ('┎', [NONE, LIGHT, HEAVY, NONE]),
+    ('┏', [NONE, HEAVY, HEAVY, NONE]),
+    ('┐', [NONE, NONE, LIGHT, LIGHT]),
+    ('┑', [NONE, NONE, LIGHT, HEAVY]),
+    ('┒', [NONE, NONE, HEAVY, LIGHT]),
+    ('┓', [NONE, NONE, HEAVY, HEAVY]),
+    ('└', [LIGHT, LIGHT, NONE, NONE]),
+    ('┕', [LIGHT, HEAVY, NONE, NONE]),
+    ('┖', [HEAVY, LIGHT, NONE, NONE]),
+    ('┗', [HEAVY, HEAVY, NONE, NONE]),
+    ('┘', [LIGHT, NONE, NONE, LIGHT]),
+    ('┙', [LIGHT, NONE, NONE, HEAVY]),
+    ('┚', [HEAVY, NONE, NONE, LIGHT]),
+    ('┛', [HEAVY, NONE, NONE, HEAVY]),
+    ('├', [LIGHT, LIGHT, LIGHT, NONE]),
+    ('┝', [LIGHT, HEAVY, LIGHT, NONE]),
+    ('┞', [HEAVY, LIGHT, LIGHT, NONE]),
+    ('┟', [LIGHT, LIGHT, HEAVY, NONE]),
+    ('┠', [HEAVY, LIGHT, HEAVY, NONE]),
+    ('┡', [HEAVY, HEAVY, LIGHT, NONE]),
+    ('┢', [LIGHT, HEAVY, HEAVY, NONE]),
+    ('┣', [HEAVY, HEAVY, HEAVY, NONE]),
+    ('┤', [LIGHT, NONE, LIGHT, LIGHT]),
+    ('┥', [LIGHT, NONE, LIGHT, HEAVY]),
+    ('┦', [HEAVY, NONE, LIGHT, LIGHT]),
+    ('┧', [LIGHT, NONE, HEAVY, LIGHT]),
+    ('┨', [HEAVY, NONE, HEAVY, LIGHT]),
+    ('┩', [HEAVY, NONE, LIGHT, HEAVY]),
+    ('┪', [LIGHT, NONE, HEAVY, HEAVY]),
+    ('┫', [HEAVY, NONE, HEAVY, HEAVY]),
+    ('┬', [NONE, LIGHT, LIGHT, LIGHT]),
+    ('┭', [NONE, LIGHT, LIGHT, HEAVY]),
+    ('┮', [NONE, HEAVY, LIGHT, LIGHT]),
+    ('┯', [NONE, HEAVY, LIGHT, HEAVY]),
+    ('┰', [NONE, LIGHT, HEAVY, LIGHT]),
+    ('┱', [NONE, LIGHT, HEAVY, HEAVY]),
+    ('┲', [NONE, HEAVY, HEAVY, LIGHT]),
+    ('┳', [NONE, HEAVY, HEAVY, HEAVY]),
+    ('┴', [LIGHT, LIGHT, NONE, LIGHT]),
+    ('┵', [LIGHT, LIGHT, NONE, HEAVY]),
+    ('┶', [LIGHT, HEAVY, NONE, LIGHT]),
+    ('┷', [LIGHT, HEAVY, NONE, HEAVY]),
+    ('┸', [HEAVY, LIGHT, NONE, LIGHT]),
+    ('┹', [HEAVY, LIGHT, NONE, HEAVY]),
+    ('┺', [HEAVY, HEAVY, NONE, LIGHT]),
+    ('┻', [HEAVY, HEAVY, NONE, HEAVY]),
+    ('┼', [LIGHT, LIGHT, LIGHT, LIGHT]),
+    ('┽', [LIGHT, LIGHT, LIGHT, HEAVY]),
+    ('┾', [LIGHT, HEAVY, LIGHT, LIGHT]),
+    ('┿', [LIGHT, HEAVY, LIGHT, HEAVY]),
+    ('╀', [HEAVY, LIGHT, LIGHT, LIGHT]),
+    ('╁', [LIGHT, LIGHT, HEAVY, LIGHT]),
+    ('╂', [HEAVY, LIGHT, HEAVY, LIGHT]),
+    ('╃', [HEAVY, LIGHT, LIGHT, HEAVY]),
+    ('╄', [HEAVY, HEAVY, LIGHT, LIGHT]),
+    ('╅', [LIGHT, LIGHT, HEAVY, HEAVY]),
+    ('╆', [LIGHT, HEAVY, HEAVY, LIGHT]),
+    ('╇', [HEAVY, HEAVY, LIGHT, HEAVY]),
+    ('╈', [LIGHT, HEAVY, HEAVY, HEAVY]),
+    ('╉', [HEAVY, LIGHT, HEAVY, HEAVY]),
+    ('╊', [HEAVY, HEAVY, HEAVY, LIGHT]),
+    ('╋', [HEAVY, HEAVY, HEAVY, HEAVY]),
+    ('═', [NONE, DOUBLE, NONE, DOUBLE]),
+    ('║', [DOUBLE, NONE, DOUBLE, NONE]),
+    ('╒', [NONE, DOUBLE, LIGHT, NONE]),
+    ('╓', [NONE, LIGHT, DOUBLE, NONE]),
+    ('╔', [NONE, DOUBLE, DOUBLE, NONE]),
+    ('╕', [NONE, NONE, LIGHT, DOUBLE]),
+    ('╖', [NONE, NONE, DOUBLE, LIGHT]),
+    ('╗', [NONE, NONE, DOUBLE, DOUBLE]),
+    ('╘', [LIGHT, DOUBLE, NONE, NONE]),
+    ('╙', [DOUBLE, LIGHT, NONE, NONE]),
+    ('╚', [DOUBLE, DOUBLE, NONE, NONE]),
+    ('╛', [LIGHT, NONE, NONE, DOUBLE]),
+    ('╜', [DOUBLE, NONE, NONE, LIGHT]),
+    ('╝', [DOUBLE, NONE, NONE, DOUBLE]),
+    ('╞', [LIGHT, DOUBLE, LIGHT, NONE]),
+    ('╟', [DOUBLE, LIGHT, DOUBLE, NONE]),
+    ('╠', [DOUBLE, DOUBLE, DOUBLE, NONE]),
+    ('╡', [LIGHT, NONE, LIGHT, DOUBLE]),
+    ('╢', [DOUBLE, NONE, DOUBLE, LIGHT]),
+    ('╣', [DOUBLE, NONE, DOUBLE, DOUBLE]),
+    ('╤', [NONE, DOUBLE, LIGHT, DOUBLE]),
+    ('╥', [NONE, LIGHT, DOUBLE, LIGHT]),
+    ('╦', [NONE, DOUBLE, DOUBLE, DOUBLE]),
+    ('╧', [LIGHT, DOUBLE, NONE, DOUBLE]),
+    ('╨', [DOUBLE, LIGHT, NONE, LIGHT]),
+    ('╩', [DOUBLE, DOUBLE, NONE, DOUBLE]),
+    ('╪', [LIGHT, DOUBLE, LIGHT, DOUBLE]),
+    ('╫', [DOUBLE, LIGHT, DOUBLE, LIGHT]),
+    ('╬', [DOUBLE, DOUBLE, DOUBLE, DOUBLE]),
+    ('╴', [NONE, NONE, NONE, LIGHT]),
+    ('╵', [LIGHT, NONE, NONE, NONE]),
+    ('╶', [NONE, LIGHT, NONE, NONE]),
+    ('╷', [NONE, NONE, LIGHT, NONE]),
+    ('╸', [NONE, NONE, NONE, HEAVY]),
+    ('╹', [HEAVY, NONE, NONE, NONE]),
+    ('╺', [NONE, HEAVY, NONE, NONE]),
+    ('╻', [NONE, NONE, HEAVY, NONE]),
+    ('╼', [NONE, HEAVY, NONE, LIGHT]),
+    ('╽', [LIGHT, NONE, HEAVY, NONE]),
+    ('╾', [NONE, LIGHT, NONE, HEAVY]),
+    ('╿', [HEAVY, NONE, LIGHT, NONE]),
+    // Only read, merged junctions use the plain characters above
+    ('╭', [NONE, LIGHT, LIGHT, NONE]),
+    ('╮', [NONE, NONE, LIGHT, LIGHT]),
+    ('╯', [LIGHT, NONE, NONE, LIGHT]),
+    ('╰', [LIGHT, LIGHT, NONE, NONE]),
+    ('┄', [NONE, LIGHT, NONE, LIGHT]),
+    ('┅', [NONE, HEAVY, NONE, HEAVY]),
+    ('┆', [LIGHT, NONE, LIGHT, NONE]),
+    ('┇', [HEAVY, NONE, HEAVY, NONE]),
+    ('┈', [NONE, LIGHT, NONE, LIGHT]),
+    ('┉', [NONE, HEAVY, NONE, HEAVY]),
+    ('┊', [LIGHT, NONE, LIGHT, NONE]),
+    ('┋', [HEAVY, NONE, HEAVY, NONE]),
+    ('╌', [NONE, LIGHT, NONE, LIGHT]),
+    ('╍', [NONE, HEAVY, NONE, HEAVY]),
+    ('╎', [LIGHT, NONE, LIGHT, NONE]),
+    ('╏', [HEAVY, NONE, HEAVY, NONE]),
+];
+
+fn arms(ch: char) -> Option<[u8; 4]> {
+    ARMS.iter().find(|(c, _)| *c == ch).map(|(_, arms)| *arms)
+}
+
+fn glyph(arms: [u8; 4]) -> Option<char> {
+    ARMS.iter().find(|(_, a)| *a == arms).map(|(c, _)| *c)
+}
+
+/// Returns the character to draw when the box drawing character `new` is drawn over `old`
+///
+/// Lines leaving either character are kept, so `┐` drawn over `┌` gives `┬`. Where both have a
+/// line in the same direction the weight of `new` wins. Mixes without a matching character,
+/// such as heavy and double lines, are drawn with light lines instead. If either character is
+/// not a box drawing character `new` is returned unchanged
+pub fn merge_junction(old: char, new: char) -> char {
+    merge_junction_end(old, new, [true; 4], [true; 4])
+}
+
+/// Returns whether a line leaves the box drawing character `ch` in a direction, numbered up,
+/// right, down, left
+pub(crate) fn has_arm(ch: char, direction: usize) -> bool {
+    arms(ch).is_some_and(|arms| arms[direction] != NONE)
+}
+
+/// Like `merge_junction`, but only the lines of `new` leaving in the directions set in `open`
+/// are added to `old`, and only the lines of `old` leaving in the directions set in `kept` are
+/// kept, both as up, right, down, left
+///
+/// Used for the ends of lines, so a line ending on a border makes a tee rather than a cross, and
+/// so lines of `old` that lead nowhere are not left sticking out of the junction
+pub(crate) fn merge_junction_end(old: char, new: char, open: [bool; 4], kept: [bool; 4]) -> char {
+    let (old_arms, new_arms) = match (arms(old), arms(new)) {
+        (Some(old_arms), Some(new_arms)) => (old_arms, new_arms),
+        _ => return new,
+    };
+    let mut merged = new_arms;
+    for i in 0..4 {
+        if (!open[i] || merged[i] == NONE) && kept[i] {
+            merged[i] = old_arms[i];
+        } else if !open[i] {
+            merged[i] = NONE;
+        }
+    }
+    // Keep rounded corners and dashed lines when nothing was added to them
+    if merged == new_arms {
+        return new;
+    }
+    if merged == old_arms {
+        return old;
+    }
+
+    // Replaces lines of at least the given weight with light ones
+    let lighten = |from: u8| {
+        let mut arms = merged;
+        for arm in arms.iter_mut() {
+            if *arm >= from {
+                *arm = LIGHT;
+            }
+        }
+        arms
+    };
+    glyph(merged)
+        .or_else(|| glyph(lighten(DOUBLE)))
+        .or_else(|| glyph(lighten(HEAVY)))
+        .unwrap_or(new)
+}
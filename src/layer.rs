@@ -0,0 +1,102 @@
+use std::ops::Range;
+
+use buffer::{blit_cells, CellBuffer};
+use grid::Grid;
+use {Inherit, Rect, TermCell};
+
+/// Refers to a layer added with [`add_layer`][::TermBuf::add_layer]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LayerId(usize);
+
+/// A buffer drawn over the base buffer when flushing
+#[derive(Debug, Clone)]
+pub(crate) struct Layer {
+    pub(crate) id: LayerId,
+    pub(crate) buffer: CellBuffer,
+    /// Layers with a higher z are drawn over those with a lower one
+    pub(crate) z: i32,
+    /// The position of the layer's top left cell on the screen, which may be off screen
+    pub(crate) x: isize,
+    pub(crate) y: isize,
+    pub(crate) visible: bool,
+}
+
+impl Layer {
+    /// Creates a transparent layer
+    pub(crate) fn new(id: usize, width: usize, height: usize, z: i32) -> Layer {
+        let mut buffer = CellBuffer::transparent(width, height);
+        // Nothing on the screen changes until the layer is drawn on
+        buffer.cells.clean();
+        Layer {
+            id: LayerId(id),
+            buffer,
+            z,
+            x: 0,
+            y: 0,
+            visible: true,
+        }
+    }
+
+    /// Returns the rows of the screen the layer covers, which may be past the bottom
+    pub(crate) fn screen_rows(&self) -> Range<usize> {
+        let top = self.y.max(0) as usize;
+        let bottom = (self.y + self.buffer.height() as isize).max(0) as usize;
+        top..bottom.max(top)
+    }
+}
+
+/// Returns `above` drawn over `below`, taking the attributes `above` inherits from `below`
+pub(crate) fn place(below: &TermCell, above: &TermCell) -> TermCell {
+    let mut cell = *above;
+    if above.inherit.contains(Inherit::Content) {
+        cell.content = below.content;
+        cell.width = below.width;
+        cell.link = below.link;
+    }
+    if above.inherit.contains(Inherit::Fg) {
+        cell.fg = below.fg;
+    }
+    if above.inherit.contains(Inherit::Bg) {
+        cell.bg = below.bg;
+    }
+    // Styles set on a cell that inherits them are added, so a shadow cast on a layer still dims
+    // what is below it
+    if above.inherit.contains(Inherit::Style) {
+        cell.style = below.style | above.style;
+        cell.underline_color = below.underline_color;
+    }
+    cell.inherit = above.inherit & below.inherit;
+    cell
+}
+
+/// Marks the rows of `base` that changed in a visible layer, so every row of the screen that
+/// may look different is dirty in `base`
+pub(crate) fn mark_dirty(base: &mut Grid, layers: &[Layer]) {
+    for layer in layers.iter().filter(|layer| layer.visible) {
+        for row in 0..layer.buffer.height() {
+            let y = layer.y + row as isize;
+            if y >= 0 && layer.buffer.cells.is_row_dirty(row) {
+                base.mark_dirty(y as usize..y as usize + 1);
+            }
+        }
+    }
+}
+
+/// Sets `frame` to the visible layers drawn over the base buffer, in the order given
+///
+/// Wide characters cut in half by a layer, or by the edge of the screen, are replaced with spaces
+pub(crate) fn compose(frame: &mut Grid, base: &Grid, layers: &[Layer]) {
+    frame.clone_from(base);
+    for layer in layers.iter().filter(|layer| layer.visible) {
+        // Parts of the layer above or left of the screen are clipped off
+        let left = (-layer.x).max(0) as usize;
+        let top = (-layer.y).max(0) as usize;
+        let (width, height) = (layer.buffer.width(), layer.buffer.height());
+        if left >= width || top >= height {
+            continue;
+        }
+        let rect = Rect::new(left, top, width - left, height - top);
+        let (x, y) = (layer.x.max(0) as usize, layer.y.max(0) as usize);
+        blit_cells(frame, &layer.buffer.cells, rect, x, y, Some);
+    }
+}
@@ -5,23 +5,52 @@ extern crate unicode_width;
 
 use unicode_width::UnicodeWidthChar;
 
+#[cfg(test)]
+use std::io::ErrorKind;
 #[cfg(not(test))]
 use std::io::{stdout, Stdout};
 use std::io::{Error, Write};
+#[cfg(test)]
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 
-use termion::color::{Bg, Fg};
 #[cfg(not(test))]
 use termion::raw::{IntoRawMode, RawTerminal};
 #[cfg(not(test))]
 use termion::screen::AlternateScreen;
 
+mod border;
+mod buffer;
 pub mod builder;
 mod color;
+mod gradient;
+mod grid;
+mod junction;
+mod layer;
+mod link;
+mod mode;
+mod packed;
+mod render;
+mod stats;
 mod style;
-pub use color::Color;
-pub use style::Style;
+mod theme;
+pub use border::{Align, BorderGlyphs, BorderStyle, Sides};
+pub use buffer::CellBuffer;
+pub use color::{Color, ParseColorError, UnderlineColor, UnderlineColorReset};
+pub use gradient::{Gradient, GradientDirection};
+pub use junction::merge_junction;
+pub use layer::LayerId;
+pub use link::LinkId;
+pub use mode::{Capabilities, ColorDepth, RenderMode};
+pub use stats::{Corner, FrameStats};
+pub use style::{Inherit, ParseStyleError, Style, StylePatch};
+pub use theme::{ParseThemeError, Theme, ThemeStyle};
 
 use builder::*;
+use grid::Grid;
+use layer::Layer;
+use packed::PackedCell;
+use render::{Pen, ScreenOp, BEGIN_SYNCHRONIZED_UPDATE, END_SYNCHRONIZED_UPDATE};
 
 /// Returns the width of a char if it is greater than zero, or one if it is zero
 pub fn display_width(ch: char) -> usize {
@@ -61,6 +90,48 @@ pub struct TermSize {
     pub height: usize,
 }
 
+/// A rectangular area of cells
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Rect {
+    /// Column of the left edge
+    pub x: usize,
+    /// Row of the top edge
+    pub y: usize,
+    /// Width in cells
+    pub width: usize,
+    /// Height in cells
+    pub height: usize,
+}
+
+impl Rect {
+    /// Creates a new `Rect`
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns whether a cell is inside the rect
+    pub(crate) fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+
+    /// Returns a rect of the given size centered in this one, shrunk to fit if needed
+    pub fn centered(&self, width: usize, height: usize) -> Rect {
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+        Rect {
+            x: self.x + (self.width - width) / 2,
+            y: self.y + (self.height - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
 /// A single cell in the terminal
 ///
 /// To create styled cells, see [`builder::CellBuilder`]
@@ -72,8 +143,16 @@ pub struct TermCell {
     pub fg: Option<Color>,
     /// The background color of the cell, if any
     pub bg: Option<Color>,
-    /// All the styles of the cell, if any
-    pub style: Option<Style>,
+    /// The underline color of the cell, if any
+    pub underline_color: Option<Color>,
+    /// All the styles of the cell, empty for the default style
+    ///
+    /// This used to be an `Option<Style>`, where `None` is now `Style::empty()`
+    pub style: Style,
+    /// Which attributes are taken from below the cell instead of the cell itself
+    pub inherit: Inherit,
+    /// The hyperlink the cell belongs to, if any
+    pub link: Option<LinkId>,
     /// The width of the character
     pub(crate) width: u8,
 }
@@ -85,7 +164,10 @@ impl TermCell {
             content: ' ',
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
+            link: None,
             width: 1,
         }
     }
@@ -96,169 +178,662 @@ impl TermCell {
             content: ch,
             fg: None,
             bg: None,
-            style: None,
+            underline_color: None,
+            style: Style::empty(),
+            inherit: Inherit::empty(),
+            link: None,
             width: display_width(ch) as u8,
         }
     }
+
+    /// Creates a cell that shows whatever is below it, the initial contents of layers
+    pub fn transparent() -> TermCell {
+        TermCell {
+            inherit: Inherit::all(),
+            ..TermCell::empty()
+        }
+    }
+}
+
+/// The raw terminal a `TermBuf` draws to
+#[cfg(not(test))]
+enum Terminal {
+    /// The alternate screen, used by default
+    AlternateScreen(AlternateScreen<RawTerminal<Stdout>>),
+    /// The main screen, used in plain mode where switching screens would emit escape sequences
+    Main(RawTerminal<Stdout>),
+    /// Any writer, see [`TermBuf::with_writer`]
+    Writer(Box<dyn Write + Send>),
+}
+
+#[cfg(not(test))]
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            Terminal::AlternateScreen(term) => term.write(buf),
+            Terminal::Main(term) => term.write(buf),
+            Terminal::Writer(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Terminal::AlternateScreen(term) => term.flush(),
+            Terminal::Main(term) => term.flush(),
+            Terminal::Writer(writer) => writer.flush(),
+        }
+    }
+}
+
+/// What tests write to instead of the terminal, which derefs to the bytes written
+#[cfg(test)]
+#[derive(Default)]
+struct TestTerminal {
+    out: Vec<u8>,
+    /// How many more bytes are written before a write fails, once
+    fail_after: Option<usize>,
+}
+
+#[cfg(test)]
+impl Write for TestTerminal {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self.fail_after {
+            Some(0) => {
+                self.fail_after = None;
+                Err(Error::from(ErrorKind::WouldBlock))
+            }
+            Some(left) => {
+                let n = left.min(buf.len());
+                self.fail_after = Some(left - n);
+                self.out.write(&buf[..n])
+            }
+            None => self.out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Deref for TestTerminal {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.out
+    }
+}
+
+#[cfg(test)]
+impl DerefMut for TestTerminal {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.out
+    }
 }
 
 /// A buffered terminal interface, using a cell-based api
+///
+/// Drawing calls go to the base [`CellBuffer`], or to the layer picked with
+/// [`draw_on`][TermBuf::draw_on]
 pub struct TermBuf {
-    /// The underlying, unbuffered, `RawTerminal`
+    /// The underlying, unbuffered, raw terminal
     #[cfg(not(test))]
-    pub terminal: AlternateScreen<RawTerminal<Stdout>>,
+    terminal: Terminal,
     #[cfg(test)]
-    pub terminal: ::std::io::Sink,
+    terminal: TestTerminal,
     /// Whether or not the cursor will be shown
     pub cursor: bool,
     /// The position of the cursor, 1 indexed
     pub cursor_pos: (usize, usize),
     /// The internal cell buffer
-    buffer: Vec<Vec<TermCell>>,
+    buffer: CellBuffer,
     /// The state of the buffer before the last write
-    prev_buffer: Vec<Vec<TermCell>>,
+    prev_buffer: Grid,
+    /// The buffer with the layers drawn over it, kept to reuse its allocation
+    frame: Grid,
+    /// Whether every row is written on the next flush, whether or not it changed
+    redraw: bool,
+    /// Scrolls and clears the terminal does itself on the next flush, already applied to
+    /// `prev_buffer`
+    screen_ops: Vec<ScreenOp>,
+    /// The output of the last flush, kept to reuse its allocation
+    out: Vec<u8>,
+    /// What the last flush wrote
+    last_stats: FrameStats,
+    /// When the last flush started
+    last_flush: Option<Instant>,
+    /// Where the frame stats are drawn, if anywhere
+    stats_overlay: Option<Corner>,
+    /// Which escape sequences are emitted
+    mode: RenderMode,
+    /// Which colors the terminal can show
+    depth: ColorDepth,
+    /// Which optional features the terminal supports
+    caps: Capabilities,
+    /// The layers drawn over the buffer, ordered by z
+    layers: Vec<Layer>,
+    /// The layer drawing calls go to, or `None` for the buffer itself
+    target: Option<LayerId>,
+    /// The id given to the next layer
+    next_layer: usize,
 }
 
 impl TermBuf {
     /// Creates a new TermBuf and switches to raw mode
+    ///
+    /// The render mode is picked from the environment, see [`RenderMode::detect`]
     pub fn init() -> Result<TermBuf, Error> {
+        #[cfg(not(test))]
+        let mode = RenderMode::detect();
+        // Tests expect the same output whatever environment they run in
+        #[cfg(test)]
+        let mode = RenderMode::Color;
+        TermBuf::init_with_mode(mode)
+    }
+
+    /// Creates a new TermBuf with a given render mode and switches to raw mode
+    ///
+    /// In `Plain` mode the alternate screen is not used
+    pub fn init_with_mode(mode: RenderMode) -> Result<TermBuf, Error> {
         let size = size()?;
-        Ok(TermBuf {
-            #[cfg(not(test))]
-            terminal: AlternateScreen::from(stdout().into_raw_mode()?),
-            #[cfg(test)]
-            terminal: ::std::io::sink(),
+        #[cfg(not(test))]
+        let terminal = match mode {
+            RenderMode::Plain => Terminal::Main(stdout().into_raw_mode()?),
+            _ => Terminal::AlternateScreen(AlternateScreen::from(stdout().into_raw_mode()?)),
+        };
+        #[cfg(test)]
+        let terminal = TestTerminal::default();
+        Ok(TermBuf::new(terminal, size, mode))
+    }
+
+    /// Creates a TermBuf of a fixed size that writes to any writer instead of the terminal
+    ///
+    /// Useful for recording output or measuring performance. `update_size` still uses the size
+    /// of the real terminal
+    #[cfg(not(test))]
+    pub fn with_writer<W: Write + Send + 'static>(
+        writer: W,
+        size: TermSize,
+        mode: RenderMode,
+    ) -> TermBuf {
+        TermBuf::new(Terminal::Writer(Box::new(writer)), size, mode)
+    }
+
+    fn new(
+        #[cfg(not(test))] terminal: Terminal,
+        #[cfg(test)] terminal: TestTerminal,
+        size: TermSize,
+        mode: RenderMode,
+    ) -> TermBuf {
+        TermBuf {
+            terminal,
             cursor: true,
             cursor_pos: (1, 1),
-            buffer: vec![vec![TermCell::empty(); size.width as usize]; size.height as usize],
-            prev_buffer: vec![vec![TermCell::empty(); size.width as usize]; size.height as usize],
-        })
+            buffer: CellBuffer::new(size.width, size.height),
+            prev_buffer: Grid::new(size.width, size.height, TermCell::empty()),
+            frame: Grid::default(),
+            redraw: false,
+            screen_ops: Vec::new(),
+            out: Vec::new(),
+            last_stats: FrameStats::default(),
+            last_flush: None,
+            stats_overlay: None,
+            mode,
+            #[cfg(not(test))]
+            depth: ColorDepth::detect(),
+            #[cfg(test)]
+            depth: ColorDepth::Ansi256,
+            #[cfg(not(test))]
+            caps: Capabilities::detect(),
+            #[cfg(test)]
+            caps: Capabilities::default(),
+            layers: Vec::new(),
+            target: None,
+            next_layer: 0,
+        }
     }
 
-    /// Writes an entire string
-    pub fn print(&mut self, x: usize, y: usize, s: &str) {
-        let mut x = x;
-        for ch in s.chars() {
-            self.set_char(x, y, ch);
-            x += display_width(ch);
+    /// Gets the render mode
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    /// Sets the render mode, the whole buffer is redrawn on the next flush
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+        self.redraw = true;
+    }
+
+    /// Gets the color depth
+    pub fn color_depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    /// Sets the color depth, the whole buffer is redrawn on the next flush
+    ///
+    /// By default the depth is picked from the environment, see [`ColorDepth::detect`]
+    pub fn set_color_depth(&mut self, depth: ColorDepth) {
+        self.depth = depth;
+        self.redraw = true;
+    }
+
+    /// Gets the terminal capabilities
+    pub fn capabilities(&self) -> Capabilities {
+        self.caps
+    }
+
+    /// Sets the terminal capabilities, the whole buffer is redrawn on the next flush
+    ///
+    /// By default the capabilities are guessed from the environment, see [`Capabilities::detect`]
+    pub fn set_capabilities(&mut self, caps: Capabilities) {
+        self.caps = caps;
+        self.redraw = true;
+    }
+
+    /// Gets the theme used by builders for roles
+    pub fn theme(&self) -> &Theme {
+        self.buffer.theme()
+    }
+
+    /// Gets the theme mutably, to change individual roles
+    ///
+    /// The theme is shared by the buffer and all layers
+    pub fn theme_mut(&mut self) -> &mut Theme {
+        self.buffer.theme_mut()
+    }
+
+    /// Replaces the theme of the buffer and all layers, returning the old one
+    ///
+    /// Only affects drawing done afterwards, existing cells keep their colors and styles
+    pub fn set_theme(&mut self, theme: Theme) -> Theme {
+        self.buffer.set_theme(theme)
+    }
+
+    /// Sets whether boxes and lines merge with box drawing characters they are drawn over, in the
+    /// buffer and all layers
+    ///
+    /// See [`CellBuffer::set_merge_junctions`]
+    pub fn set_merge_junctions(&mut self, merge: bool) {
+        self.buffer.set_merge_junctions(merge);
+    }
+
+    /// Adds a layer covering an area of the screen, drawn over the buffer and any layers with a
+    /// lower `z`
+    ///
+    /// Layers start out transparent, and are drawn to after selecting them with
+    /// [`draw_on`][TermBuf::draw_on]. Transparent cells, and cells that inherit some attributes
+    /// with [`TermCell::inherit`], show what is below them. Layers with the same `z` are drawn in
+    /// the order they were added
+    pub fn add_layer(&mut self, rect: Rect, z: i32) -> LayerId {
+        let mut layer = Layer::new(self.next_layer, rect.width, rect.height, z);
+        layer.x = rect.x as isize;
+        layer.y = rect.y as isize;
+        layer.buffer.share_settings(&self.buffer);
+        self.next_layer += 1;
+        let id = layer.id;
+        self.layers.push(layer);
+        self.layers.sort_by_key(|layer| layer.z);
+        id
+    }
+
+    /// Removes a layer, uncovering what was below it on the next flush
+    ///
+    /// Returns false if there was no such layer
+    pub fn remove_layer(&mut self, id: LayerId) -> bool {
+        self.mark_layer(id);
+        let len = self.layers.len();
+        self.layers.retain(|layer| layer.id != id);
+        if self.target == Some(id) {
+            self.target = None;
         }
+        self.layers.len() != len
     }
 
-    /// Writes a single char
-    pub fn set_char(&mut self, x: usize, y: usize, ch: char) {
-        if let Some(line) = self.buffer.get_mut(y) {
-            if let Some(mut old_ch) = line.get_mut(x) {
-                *old_ch = TermCell::with_char(ch);
-            }
+    /// Sets the z of a layer
+    pub fn set_layer_z(&mut self, id: LayerId, z: i32) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.z = z;
         }
+        self.layers.sort_by_key(|layer| layer.z);
+        self.mark_layer(id);
     }
 
-    /// Writes a single cell
-    pub fn set_cell(&mut self, x: usize, y: usize, cell: TermCell) {
-        if let Some(line) = self.buffer.get_mut(y) {
-            if let Some(mut old_ch) = line.get_mut(x) {
-                *old_ch = cell;
-            }
+    /// Moves the top left corner of a layer, which may be off screen
+    pub fn move_layer(&mut self, id: LayerId, x: isize, y: isize) {
+        self.mark_layer(id);
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.x = x;
+            layer.y = y;
         }
+        self.mark_layer(id);
     }
 
-    /// Replaces the forground of a cell
-    pub fn set_cell_fg(&mut self, x: usize, y: usize, fg: Color) {
-        if let Some(line) = self.buffer.get_mut(y) {
-            if let Some(mut old_cell) = line.get_mut(x) {
-                old_cell.fg = Some(fg);
-            }
+    /// Shows or hides a layer, hidden layers keep their cells
+    pub fn set_layer_visible(&mut self, id: LayerId, visible: bool) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.visible = visible;
         }
+        self.mark_layer(id);
     }
 
-    /// Replaces the background of a cell
-    pub fn set_cell_bg(&mut self, x: usize, y: usize, bg: Color) {
-        if let Some(line) = self.buffer.get_mut(y) {
-            if let Some(mut old_cell) = line.get_mut(x) {
-                old_cell.bg = Some(bg);
-            }
+    /// Marks the rows a layer covers dirty, for when it is changed as a whole
+    fn mark_layer(&mut self, id: LayerId) {
+        if let Some(layer) = self.layers.iter().find(|layer| layer.id == id) {
+            self.buffer.cells.mark_dirty(layer.screen_rows());
         }
     }
 
-    /// Replaces the style of a cell
-    pub fn set_cell_style(&mut self, x: usize, y: usize, style: Style) {
-        if let Some(line) = self.buffer.get_mut(y) {
-            if let Some(mut old_cell) = line.get_mut(x) {
-                old_cell.style = Some(style);
-            }
+    /// Sends drawing calls to a layer, or back to the buffer with `None`
+    ///
+    /// Coordinates are relative to the layer's top left corner
+    pub fn draw_on(&mut self, target: Option<LayerId>) {
+        self.target = target;
+    }
+
+    /// Returns the position of the layer being drawn on in `layers`
+    fn target_index(&self) -> Option<usize> {
+        let target = self.target?;
+        self.layers.iter().position(|layer| layer.id == target)
+    }
+
+    /// Clears the screen and forgets what it shows, so the next flush draws everything again
+    ///
+    /// Use this when something else wrote to the terminal, for example on Ctrl-L
+    pub fn invalidate(&mut self) {
+        if self.mode == RenderMode::Plain {
+            self.redraw = true;
+            return;
         }
+        self.prev_buffer.fill(TermCell::empty());
+        self.buffer.cells.mark_dirty(0..self.buffer.height());
+        self.screen_ops.push(ScreenOp::ClearAll);
     }
 
-    /// Writes a single char with color builder
-    pub fn char_builder(&mut self, x: usize, y: usize, ch: char) -> StyleCellBuilder {
-        StyleCellBuilder::new(&mut self.buffer, x, y, ch.to_string())
+    /// Clears an area of the screen and forgets what it shows, so the next flush draws it again
+    ///
+    /// See [`invalidate`][TermBuf::invalidate]
+    pub fn invalidate_region(&mut self, rect: Rect) {
+        // A resized buffer invalidates the whole screen
+        if self.fit_to_buffer() {
+            return;
+        }
+        if self.mode == RenderMode::Plain {
+            self.redraw = true;
+            return;
+        }
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        let (left, top) = (rect.x.min(width), rect.y.min(height));
+        let rect = Rect::new(
+            left,
+            top,
+            (rect.x + rect.width).min(width) - left,
+            (rect.y + rect.height).min(height) - top,
+        );
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.prev_buffer.fill_rect(rect, TermCell::empty());
+        self.buffer.cells.mark_dirty(rect.y..rect.y + rect.height);
+        self.screen_ops.push(ScreenOp::Clear(rect));
     }
 
-    /// Writes a string with color builder
-    pub fn string_builder(&mut self, x: usize, y: usize, s: &str) -> StyleCellBuilder {
-        StyleCellBuilder::new(&mut self.buffer, x, y, s.to_owned())
+    /// Resizes the screen state to the buffer when the buffer was resized through
+    /// [`buffer_mut`][TermBuf::buffer_mut], and invalidates the whole screen
+    ///
+    /// Returns whether the size changed
+    fn fit_to_buffer(&mut self) -> bool {
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        if self.prev_buffer.width() == width && self.prev_buffer.height() == height {
+            return false;
+        }
+        self.prev_buffer.resize(width, height, TermCell::empty());
+        self.frame.resize(width, height, TermCell::empty());
+        // Pending scrolls and clears were for the old size
+        self.screen_ops.clear();
+        self.invalidate();
+        true
+    }
+
+    /// Returns whether anything was drawn, or anything else changed that needs writing, since the
+    /// last flush
+    ///
+    /// Apps that are idle can skip flushing when this is false. Only the cells are tracked, so
+    /// moving the cursor does not make the buffer dirty
+    pub fn is_dirty(&self) -> bool {
+        self.redraw
+            || !self.screen_ops.is_empty()
+            || self.buffer.cells.is_dirty()
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.visible && layer.buffer.cells.is_dirty())
+    }
+
+    /// Marks every row as written, in the buffer and the layers
+    fn clean(&mut self) {
+        self.buffer.cells.clean();
+        for layer in &mut self.layers {
+            layer.buffer.cells.clean();
+        }
     }
 
     /// Flushes the internal buffer to the terminal
-    pub fn flush(&mut self) -> Result<(), Error> {
-        for (y, line) in self.buffer.iter().enumerate() {
+    ///
+    /// Only the rows that were drawn on since the last flush are compared with what the
+    /// terminal shows, see [`is_dirty`][TermBuf::is_dirty]. Returns what was written. If writing
+    /// fails, the next flush writes the whole frame again
+    pub fn flush(&mut self) -> Result<FrameStats, Error> {
+        let start = Instant::now();
+        let mut stats = FrameStats::default();
+        self.fit_to_buffer();
+        // The frame is written to the terminal all at once, from a buffer that is reused
+        let mut out = ::std::mem::take(&mut self.out);
+        out.clear();
+        let dirty = self.is_dirty();
+        layer::mark_dirty(&mut self.buffer.cells, &self.layers);
+        // When nothing changed the last frame is still current, and is kept
+        let composed = dirty && (!self.layers.is_empty() || self.stats_overlay.is_some());
+        if composed {
+            layer::compose(&mut self.frame, &self.buffer.cells, &self.layers);
+        }
+        if let (true, Some(corner)) = (dirty, self.stats_overlay) {
+            let fps = self
+                .last_flush
+                .map(|last| start.duration_since(last).as_secs_f64())
+                .filter(|&secs| secs > 0.0)
+                .map_or(0.0, |secs| 1.0 / secs);
+            stats::draw_overlay(&mut self.frame, corner, fps, self.last_stats);
+            let y = corner.row(self.frame.height());
+            self.buffer.cells.mark_dirty(y..y + 1);
+        }
+        let frame = if composed {
+            &self.frame
+        } else {
+            &self.buffer.cells
+        };
+        if self.mode == RenderMode::Plain {
+            self.screen_ops.clear();
+            let redraw = ::std::mem::replace(&mut self.redraw, false);
+            if dirty {
+                stats.rows_examined = frame.height();
+                stats.cells_changed =
+                    TermBuf::flush_plain(&mut out, &mut self.prev_buffer, frame, redraw)?;
+            }
+            self.clean();
+            return self.write_frame(out, stats, start);
+        }
+
+        // Terminals that support it hold the screen until the whole frame is written
+        let synchronized = dirty && self.caps.synchronized_output;
+        if synchronized {
+            write!(out, "{}", BEGIN_SYNCHRONIZED_UPDATE)?;
+        }
+        // Only the rows the scrolls left behind, or that were cleared, differ from `prev_buffer`
+        let width = self.buffer.width();
+        for op in self.screen_ops.drain(..) {
+            if !self.redraw {
+                op.write(&mut out, width)?;
+            }
+        }
+        // Locked once for the frame rather than for every link
+        let links = link::lock(&self.buffer.links);
+        let empty = PackedCell::new(&TermCell::empty());
+        for (y, line) in frame.rows().enumerate() {
+            if !self.redraw && !self.buffer.cells.is_row_dirty(y) {
+                continue;
+            }
+            stats.rows_examined += 1;
             // If the buffer line is empty, make sure the line is empty in the terminal
-            if line.iter().all(|x| *x == TermCell::empty()) {
+            if line.iter().all(|x| *x == empty) {
                 write!(
-                    self.terminal,
+                    out,
                     "{}{}",
                     termion::cursor::Goto(1, y as u16 + 1),
                     termion::clear::CurrentLine
                 )?;
             }
 
-            if Some(line) != self.prev_buffer.get(y) {
-                write!(self.terminal, "{}", termion::cursor::Goto(1, y as u16 + 1))?;
+            if self.redraw || Some(line) != self.prev_buffer.row(y) {
+                stats.cells_changed += self.prev_buffer.changed_cells(y, line);
+                write!(out, "{}", termion::cursor::Goto(1, y as u16 + 1))?;
                 let mut x = 0;
+                let mut link = None;
+                let mut pen = Pen::default();
+                let mut last = None;
                 while x < line.len() {
-                    let cell = &line[x];
-                    let mut has_fg = false;
-                    let mut has_bg = false;
-                    let mut has_style = false;
-                    if let Some(fg) = cell.fg {
-                        write!(self.terminal, "{}", Fg(fg))?;
-                        has_fg = true;
+                    // Cells that look like the one before only need their content written
+                    if let Some(prev) = last {
+                        if line[x].same_look(prev) {
+                            write!(out, "{}", line[x].content())?;
+                            x += line[x].width();
+                            continue;
+                        }
                     }
-                    if let Some(bg) = cell.bg {
-                        write!(self.terminal, "{}", Bg(bg))?;
-                        has_bg = true;
+                    last = Some(line[x]);
+                    let cell = line[x].unpack();
+                    let cell_link = cell.link.filter(|_| self.caps.hyperlinks);
+                    if cell_link != link {
+                        if link.is_some() {
+                            write!(out, "{}", link::CLOSE)?;
+                        }
+                        if let Some(id) = cell_link {
+                            let target = links.get(id).unwrap_or_default();
+                            link::write_open(&mut out, target, id)?;
+                        }
+                        link = cell_link;
                     }
-                    if let Some(style) = &cell.style {
-                        write!(self.terminal, "{}", style)?;
-                        has_style = true;
-                    }
-                    write!(self.terminal, "{}", cell.content)?;
-                    if has_fg {
-                        write!(self.terminal, "{}", Fg(termion::color::Reset))?;
-                    }
-                    if has_bg {
-                        write!(self.terminal, "{}", Bg(termion::color::Reset))?;
-                    }
-                    if has_style {
-                        write!(self.terminal, "{}", termion::style::Reset)?;
+                    let cell = self.mode.apply(&cell);
+                    pen.change(&mut out, Pen::new(&cell, self.depth, self.caps))?;
+                    write!(out, "{}", cell.content)?;
+                    x += line[x].width();
+                }
+                if link.is_some() {
+                    write!(out, "{}", link::CLOSE)?;
+                }
+                pen.change(&mut out, Pen::default())?;
+                // A composed frame is swapped in whole below
+                if !composed {
+                    if let Some(old_line) = self.prev_buffer.row_mut(y) {
+                        old_line.copy_from_slice(line);
                     }
-                    x += line[x].width as usize;
                 }
-                if let Some(mut old_line) = self.prev_buffer.get_mut(y) {
-                    *old_line = line.clone();
-                };
             }
         }
+        drop(links);
+        if composed {
+            // The old state becomes the space the next frame is composed in
+            ::std::mem::swap(&mut self.prev_buffer, &mut self.frame);
+        }
+        self.redraw = false;
+        self.clean();
 
         if self.cursor {
             write!(
-                self.terminal,
+                out,
                 "{}",
                 termion::cursor::Goto(self.cursor_pos.0 as u16, self.cursor_pos.1 as u16)
             )?;
         }
-        self.terminal.flush()?;
-        Ok(())
+        if synchronized {
+            write!(out, "{}", END_SYNCHRONIZED_UPDATE)?;
+        }
+        self.write_frame(out, stats, start)
+    }
+
+    /// Writes a frame to the terminal in one go, keeping the buffer for the next frame, and
+    /// returns its finished stats
+    fn write_frame(
+        &mut self,
+        out: Vec<u8>,
+        mut stats: FrameStats,
+        start: Instant,
+    ) -> Result<FrameStats, Error> {
+        stats.bytes = out.len();
+        stats.escape_sequences = stats::count_escapes(&out);
+        let result = self
+            .terminal
+            .write_all(&out)
+            .and_then(|()| self.terminal.flush());
+        self.out = out;
+        if let Err(err) = result {
+            // The terminal may show only part of the frame, so all of it is written again
+            self.redraw = true;
+            return Err(err);
+        }
+        stats.duration = start.elapsed();
+        self.last_stats = stats;
+        self.last_flush = Some(start);
+        Ok(stats)
+    }
+
+    /// Returns what the last flush wrote, see [`flush`][TermBuf::flush]
+    pub fn frame_stats(&self) -> FrameStats {
+        self.last_stats
+    }
+
+    /// Draws the frame rate and the bytes written by the last flush in a corner of the screen,
+    /// or stops drawing them with `None`
+    ///
+    /// The counter is drawn over everything when flushing, the buffer itself is not changed. It
+    /// is only updated when something else was drawn
+    pub fn set_stats_overlay(&mut self, corner: Option<Corner>) {
+        for corner in self.stats_overlay.iter().chain(corner.iter()) {
+            let y = corner.row(self.buffer.height());
+            self.buffer.cells.mark_dirty(y..y + 1);
+        }
+        self.stats_overlay = corner;
+    }
+
+    /// Writes every line as plain text if anything changed, without any escape sequences
+    ///
+    /// Returns the number of cells that changed
+    fn flush_plain<W: Write>(
+        out: &mut W,
+        prev_buffer: &mut Grid,
+        frame: &Grid,
+        redraw: bool,
+    ) -> Result<usize, Error> {
+        if !redraw && frame == prev_buffer {
+            return Ok(0);
+        }
+        let changed = frame
+            .rows()
+            .enumerate()
+            .map(|(y, line)| prev_buffer.changed_cells(y, line))
+            .sum();
+        for line in frame.rows() {
+            let mut text = String::with_capacity(line.len());
+            let mut x = 0;
+            while x < line.len() {
+                let cell = line[x].unpack();
+                text.push(cell.content);
+                x += cell.width as usize;
+            }
+            write!(out, "{}\r\n", text.trim_end())?;
+        }
+        prev_buffer.clone_from(frame);
+        Ok(changed)
     }
 
     /// Resizes the internal buffers if the terminal has changed size
@@ -267,21 +842,49 @@ impl TermBuf {
     pub fn update_size(&mut self) -> Result<(), Error> {
         let new_size = self.size()?;
 
-        self.buffer = vec![vec![TermCell::empty(); new_size.width]; new_size.height];
-        self.prev_buffer = vec![vec![TermCell::empty(); new_size.width]; new_size.height];
+        self.buffer.resize(new_size.width, new_size.height);
+        self.prev_buffer
+            .resize(new_size.width, new_size.height, TermCell::empty());
+        self.screen_ops.clear();
         Ok(())
     }
 
     /// Sets cursor visiblity
     pub fn set_cursor_visible(&mut self, visible: bool) -> Result<(), Error> {
         self.cursor = visible;
-        if visible {
+        if self.mode == RenderMode::Plain {
+            Ok(())
+        } else if visible {
             write!(self.terminal, "{}", termion::cursor::Show)
         } else {
             write!(self.terminal, "{}", termion::cursor::Hide)
         }
     }
 
+    /// Temporarily leaves raw mode, for example to run another program
+    ///
+    /// Does nothing when writing to a writer given to [`with_writer`][TermBuf::with_writer]
+    pub fn suspend_raw_mode(&self) -> Result<(), Error> {
+        #[cfg(not(test))]
+        match &self.terminal {
+            Terminal::AlternateScreen(term) => term.suspend_raw_mode()?,
+            Terminal::Main(term) => term.suspend_raw_mode()?,
+            Terminal::Writer(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Switches back to raw mode after [`suspend_raw_mode`][TermBuf::suspend_raw_mode]
+    pub fn activate_raw_mode(&self) -> Result<(), Error> {
+        #[cfg(not(test))]
+        match &self.terminal {
+            Terminal::AlternateScreen(term) => term.activate_raw_mode()?,
+            Terminal::Main(term) => term.activate_raw_mode()?,
+            Terminal::Writer(_) => {}
+        }
+        Ok(())
+    }
+
     /// Sets cursor position, uses 1 based coordinates
     pub fn set_cursor_position(&mut self, x: usize, y: usize) {
         self.cursor_pos = (x, y);
@@ -292,62 +895,225 @@ impl TermBuf {
         size()
     }
 
-    /// Draws a simple (unstyled) unicode box
-    pub fn draw_box(&mut self, x: usize, y: usize, width: usize, height: usize) {
-        let width = width + 1;
-        let height = height + 1;
-        self.set_char(x, y, '┌');
-        self.set_char(x + width, y, '┐');
-        self.set_char(x, y + height, '└');
-        self.set_char(x + width, y + height, '┘');
+    /// Gets the buffer drawing calls go to, the base buffer or the layer being drawn on
+    pub fn buffer(&self) -> &CellBuffer {
+        match self.target_index() {
+            Some(i) => &self.layers[i].buffer,
+            None => &self.buffer,
+        }
+    }
 
-        for i in (x + 1)..(width + x) {
-            self.set_char(i, y, '─');
-            self.set_char(i, y + height, '─');
+    /// Gets the buffer drawing calls go to mutably, for code drawing to any `CellBuffer`
+    pub fn buffer_mut(&mut self) -> &mut CellBuffer {
+        match self.target_index() {
+            Some(i) => {
+                let layer = &mut self.layers[i].buffer;
+                layer.share_settings(&self.buffer);
+                layer
+            }
+            None => &mut self.buffer,
         }
+    }
+
+    /// Writes an entire string
+    pub fn print(&mut self, x: usize, y: usize, s: &str) {
+        self.buffer_mut().print(x, y, s);
+    }
+
+    /// Writes a single char
+    pub fn set_char(&mut self, x: usize, y: usize, ch: char) {
+        self.buffer_mut().set_char(x, y, ch);
+    }
+
+    /// Writes a single cell
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: TermCell) {
+        self.buffer_mut().set_cell(x, y, cell);
+    }
+
+    /// Replaces the forground of a cell
+    pub fn set_cell_fg(&mut self, x: usize, y: usize, fg: Color) {
+        self.buffer_mut().set_cell_fg(x, y, fg);
+    }
+
+    /// Replaces the background of a cell
+    pub fn set_cell_bg(&mut self, x: usize, y: usize, bg: Color) {
+        self.buffer_mut().set_cell_bg(x, y, bg);
+    }
+
+    /// Replaces the underline color of a cell
+    pub fn set_cell_underline_color(&mut self, x: usize, y: usize, color: Color) {
+        self.buffer_mut().set_cell_underline_color(x, y, color);
+    }
+
+    /// Replaces the hyperlink of a cell
+    pub fn set_cell_link(&mut self, x: usize, y: usize, link: Option<LinkId>) {
+        self.buffer_mut().set_cell_link(x, y, link);
+    }
+
+    /// Replaces the style of a cell
+    pub fn set_cell_style(&mut self, x: usize, y: usize, style: Style) {
+        self.buffer_mut().set_cell_style(x, y, style);
+    }
+
+    /// Sets every cell in an area to `cell`
+    ///
+    /// Wide characters are repeated every other column
+    pub fn fill(&mut self, rect: Rect, cell: TermCell) {
+        self.buffer_mut().fill(rect, cell);
+    }
 
-        for i in y + 1..height + y {
-            self.set_char(x, i, '│');
-            self.set_char(x + width, i, '│');
+    /// Empties every cell in an area
+    ///
+    /// Cells of layers are made transparent
+    pub fn clear_region(&mut self, rect: Rect) {
+        self.buffer_mut().clear_region(rect);
+    }
+
+    /// Applies a patch to a cell, keeping its content and anything the patch doesn't change
+    pub fn patch_cell(&mut self, x: usize, y: usize, patch: &StylePatch) {
+        self.buffer_mut().patch_cell(x, y, patch);
+    }
+
+    /// Applies a patch to every cell in an area
+    pub fn patch_region(&mut self, rect: Rect, patch: &StylePatch) {
+        self.buffer_mut().patch_region(rect, patch);
+    }
+
+    /// Replaces the background of every cell in an area with a gradient
+    pub fn set_bg_gradient(&mut self, rect: Rect, gradient: &Gradient) {
+        self.buffer_mut().set_bg_gradient(rect, gradient);
+    }
+
+    /// Copies an area of an offscreen buffer, placing its top left corner at `x`, `y`
+    ///
+    /// See [`CellBuffer::blit`]
+    pub fn blit(&mut self, buffer: &CellBuffer, src: Rect, x: usize, y: usize) {
+        self.buffer_mut().blit(buffer, src, x, y);
+    }
+
+    /// Moves the content of an area up by `n` rows, or down if `n` is negative
+    ///
+    /// When drawing on the buffer and the area spans the full width, the next flush has the
+    /// terminal scroll the rows itself, so only the rows left behind are written.
+    /// See [`CellBuffer::scroll_region`]
+    pub fn scroll_region(&mut self, rect: Rect, n: isize) {
+        self.buffer_mut().scroll_region(rect, n);
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        let (top, bottom) = (rect.y.min(height), (rect.y + rect.height).min(height));
+        let full_width = rect.x == 0 && rect.width >= width;
+        // Terminals ignore scroll regions of one row and would scroll the whole screen, so those
+        // rows are redrawn instead
+        if self.target_index().is_some() || !full_width || bottom - top < 2 || n == 0 {
+            return;
+        }
+        if self.mode != RenderMode::Plain {
+            self.prev_buffer.scroll(rect, n, TermCell::empty());
+            self.screen_ops.push(ScreenOp::Scroll { top, bottom, n });
         }
     }
 
+    /// Returns the id of a hyperlink target, for use in [`TermCell::link`]
+    pub fn intern_link(&mut self, target: &str) -> LinkId {
+        self.buffer.intern_link(target)
+    }
+
+    /// Returns the target of a hyperlink id
+    pub fn link_target(&self, link: LinkId) -> Option<String> {
+        self.buffer.link_target(link)
+    }
+
+    /// Forgets the hyperlink targets no cell links to any more, so the link table doesn't keep
+    /// growing as targets come and go
+    ///
+    /// The links left get new ids, so ids from before are no longer valid. The cells of the
+    /// buffer, the layers and the screen are updated, so nothing is redrawn
+    pub fn compact_links(&mut self) {
+        let links = self.buffer.links.clone();
+        let mut grids = vec![&mut self.buffer.cells, &mut self.prev_buffer];
+        grids.extend(self.layers.iter_mut().map(|layer| &mut layer.buffer.cells));
+        link::lock(&links).compact(&mut grids);
+    }
+
+    /// Returns a builder for a styled cell, whose roles come from the theme
+    pub fn cell_builder(&self, content: char) -> CellBuilder {
+        self.buffer.cell_builder(content)
+    }
+
+    /// Writes a single char with color builder
+    pub fn char_builder(&mut self, x: usize, y: usize, ch: char) -> StyleCellBuilder<'_> {
+        self.buffer_mut().char_builder(x, y, ch)
+    }
+
+    /// Writes a string with color builder
+    pub fn string_builder(&mut self, x: usize, y: usize, s: &str) -> StyleCellBuilder<'_> {
+        self.buffer_mut().string_builder(x, y, s)
+    }
+
+    /// Draws a simple (unstyled) unicode box
+    pub fn draw_box(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.buffer_mut().draw_box(x, y, width, height);
+    }
+
+    /// Returns the area covered by the buffer, or by the layer being drawn on
+    pub fn area(&self) -> Rect {
+        self.buffer().area()
+    }
+
+    /// Creates a builder to draw a popup box, centered on the screen
+    ///
+    /// The size is that of the interior like [`box_builder`][TermBuf::box_builder]. The interior
+    /// is cleared and a shadow is cast on the cells to the right and below
+    pub fn popup(&mut self, width: usize, height: usize) -> BoxBuilder<'_> {
+        self.buffer_mut().popup(width, height)
+    }
+
+    /// Creates a builder to draw a popup box, centered in an area
+    ///
+    /// See [`popup`][TermBuf::popup]
+    pub fn popup_in(&mut self, rect: Rect, width: usize, height: usize) -> BoxBuilder<'_> {
+        self.buffer_mut().popup_in(rect, width, height)
+    }
+
     /// Creates a builder to draw a styled box
-    pub fn box_builder(&mut self, x: usize, y: usize, width: usize, height: usize) -> BoxBuilder {
-        BoxBuilder::new(&mut self.buffer, x, y, width, height)
+    pub fn box_builder(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> BoxBuilder<'_> {
+        self.buffer_mut().box_builder(x, y, width, height)
     }
 
     /// Draws a simple (unstyled) vertical line
     pub fn draw_vertical_line(&mut self, x: usize, y: usize, len: usize) {
-        for i in y..len + y {
-            self.set_char(x, i, '│');
-        }
+        self.buffer_mut().draw_vertical_line(x, y, len);
     }
 
     /// Draws a simple (unstyled) horizontal line
     pub fn draw_horiztonal_line(&mut self, x: usize, y: usize, len: usize) {
-        for i in x..(len + x) {
-            self.set_char(i, y, '─');
-        }
+        self.buffer_mut().draw_horiztonal_line(x, y, len);
     }
 
     /// Creates a builder to draw a styled line
-    pub fn line_builder(&mut self, x: usize, y: usize, len: usize) -> LineBuilder {
-        LineBuilder::new(&mut self.buffer, x, y, len)
+    pub fn line_builder(&mut self, x: usize, y: usize, len: usize) -> LineBuilder<'_> {
+        self.buffer_mut().line_builder(x, y, len)
     }
 
-    /// Empties buffer
+    /// Empties buffer, or makes the layer being drawn on transparent
     pub fn clear(&mut self) -> Result<(), Error> {
-        let blank = TermCell::empty();
-        for y in &mut self.buffer {
-            for cell in y {
-                *cell = blank;
-            }
-        }
+        self.buffer_mut().clear();
         Ok(())
     }
 }
 
+/// `TermBuf` can be moved to a thread that renders
+fn _assert_send<T: Send>() {}
+
+fn _term_buf_is_send() {
+    _assert_send::<TermBuf>();
+}
+
 impl Drop for TermBuf {
     fn drop(&mut self) {
         if !self.cursor {
@@ -358,7 +1124,20 @@ impl Drop for TermBuf {
 
 #[cfg(test)]
 mod test {
-    use super::{TermBuf, TermCell};
+    use builder::CellBuilder;
+    use packed::PackedCell;
+
+    use super::{
+        Align, BorderStyle, Capabilities, CellBuffer, Color, ColorDepth, Corner, Gradient, Inherit,
+        Rect, RenderMode, Sides, Style, StylePatch, TermBuf, TermCell, Theme, ThemeStyle,
+    };
+
+    /// Returns the content of the first `width` cells of a row
+    fn row(term: &TermBuf, y: usize, width: usize) -> String {
+        (0..width)
+            .map(|x| term.buffer.get(x, y).unwrap().content)
+            .collect()
+    }
 
     #[test]
     fn init() {
@@ -376,22 +1155,28 @@ mod test {
     fn set_char() {
         let mut term = TermBuf::init().unwrap();
         term.set_char(0, 0, '*');
-        assert_eq!(term.buffer[0][0], TermCell::with_char('*'));
+        assert_eq!(term.buffer.get(0, 0).unwrap(), TermCell::with_char('*'));
 
         // Test change buffer
         term.flush().unwrap();
-        assert_eq!(term.prev_buffer[0][0], TermCell::with_char('*'));
+        assert_eq!(
+            term.prev_buffer.get(0, 0).unwrap(),
+            TermCell::with_char('*')
+        );
     }
 
     #[test]
     fn set_char_pos() {
         let mut term = TermBuf::init().unwrap();
         term.set_char(5, 5, '*');
-        assert_eq!(term.buffer[5][5], TermCell::with_char('*'));
+        assert_eq!(term.buffer.get(5, 5).unwrap(), TermCell::with_char('*'));
 
         // Test change buffer
         term.flush().unwrap();
-        assert_eq!(term.prev_buffer[5][5], TermCell::with_char('*'));
+        assert_eq!(
+            term.prev_buffer.get(5, 5).unwrap(),
+            TermCell::with_char('*')
+        );
     }
 
     #[test]
@@ -401,13 +1186,13 @@ mod test {
         term.print(0, 0, test_str);
 
         for (i, ch) in test_str.chars().enumerate() {
-            assert_eq!(term.buffer[0][i], TermCell::with_char(ch))
+            assert_eq!(term.buffer.get(i, 0).unwrap(), TermCell::with_char(ch))
         }
 
         // Test change buffer
         term.flush().unwrap();
         for (i, ch) in test_str.chars().enumerate() {
-            assert_eq!(term.prev_buffer[0][i], TermCell::with_char(ch))
+            assert_eq!(term.prev_buffer.get(i, 0).unwrap(), TermCell::with_char(ch))
         }
     }
 
@@ -418,13 +1203,16 @@ mod test {
         term.print(5, 5, test_str);
 
         for (i, ch) in test_str.chars().enumerate() {
-            assert_eq!(term.buffer[5][i + 5], TermCell::with_char(ch))
+            assert_eq!(term.buffer.get(i + 5, 5).unwrap(), TermCell::with_char(ch))
         }
 
         // Test change buffer
         term.flush().unwrap();
         for (i, ch) in test_str.chars().enumerate() {
-            assert_eq!(term.prev_buffer[5][i + 5], TermCell::with_char(ch))
+            assert_eq!(
+                term.prev_buffer.get(i + 5, 5).unwrap(),
+                TermCell::with_char(ch)
+            )
         }
     }
 
@@ -433,13 +1221,976 @@ mod test {
         let mut term = TermBuf::init().unwrap();
         term.set_char(5, 5, '*');
         term.flush().unwrap();
-        assert_eq!(term.buffer[5][5], TermCell::with_char('*'));
+        assert_eq!(term.buffer.get(5, 5).unwrap(), TermCell::with_char('*'));
 
         term.clear().unwrap();
-        for y in &term.buffer {
-            for x in y {
-                assert_eq!(x, &TermCell::empty())
+        for y in 0..term.buffer.height() {
+            for x in 0..term.buffer.width() {
+                assert_eq!(term.buffer.get(x, y).unwrap(), TermCell::empty())
             }
         }
     }
+
+    #[test]
+    fn monochrome() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Monochrome).unwrap();
+        term.string_builder(0, 0, "sel")
+            .fg(Color::Red)
+            .bg(Color::Blue)
+            .draw();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[38;5;"));
+        assert!(!out.contains("\x1b[48;5;"));
+        assert!(out.contains(&Style::Invert.to_string()));
+    }
+
+    #[test]
+    fn plain() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Plain).unwrap();
+        term.string_builder(2, 1, "foo").fg(Color::Red).draw();
+        term.set_cursor_visible(false).unwrap();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains('\x1b'));
+        assert!(out.starts_with("\r\n  foo\r\n\r\n"));
+
+        // Nothing changed, nothing is written
+        term.terminal.clear();
+        term.flush().unwrap();
+        assert!(term.terminal.is_empty());
+    }
+
+    #[test]
+    fn bg_gradient() {
+        let mut term = TermBuf::init().unwrap();
+        let black = Color::Rgb(0, 0, 0);
+        let white = Color::Rgb(255, 255, 255);
+        term.print(1, 1, "abc");
+        term.set_bg_gradient(
+            Rect::new(1, 1, 3, 2),
+            Gradient::new(black, white).vertical(),
+        );
+        assert_eq!(term.buffer.get(1, 1).unwrap().content, 'a');
+        assert_eq!(term.buffer.get(3, 1).unwrap().bg, Some(black));
+        assert_eq!(term.buffer.get(1, 2).unwrap().bg, Some(white));
+        assert_eq!(term.buffer.get(1, 3).unwrap().bg, None);
+
+        // Areas past the edge are clipped, without moving the gradient
+        term.set_bg_gradient(
+            Rect::new(78, 0, 5, usize::MAX),
+            &Gradient::new(black, white),
+        );
+        assert_eq!(term.buffer.get(78, 0).unwrap().bg, Some(black));
+        assert_eq!(
+            term.buffer.get(79, 24).unwrap().bg,
+            Some(Gradient::new(black, white).at_cell(1, 0, 5, 1))
+        );
+    }
+
+    #[test]
+    fn fg_gradient() {
+        let mut term = TermBuf::init().unwrap();
+        let gradient = Gradient::evenly(&[Color::Red, Color::Green, Color::Blue]);
+        term.string_builder(0, 0, "abcde")
+            .fg_gradient(&gradient)
+            .draw();
+        assert_eq!(
+            term.buffer.get(0, 0).unwrap().fg,
+            Some(Color::Rgb(205, 0, 0))
+        );
+        assert_eq!(
+            term.buffer.get(2, 0).unwrap().fg,
+            Some(Color::Rgb(0, 205, 0))
+        );
+        assert_eq!(
+            term.buffer.get(4, 0).unwrap().fg,
+            Some(Color::Rgb(0, 0, 238))
+        );
+    }
+
+    #[test]
+    fn downsample() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.set_color_depth(ColorDepth::Ansi256);
+        term.string_builder(0, 0, "x")
+            .fg(Color::Rgb(255, 0, 0))
+            .draw();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[38;2;"));
+        assert!(out.contains("\x1b[38;5;196m"));
+    }
+
+    #[test]
+    fn detect_depth() {
+        // Without `COLORTERM` rgb colors are still written as they are
+        ::std::env::remove_var("COLORTERM");
+        ::std::env::remove_var("TERM_PROGRAM");
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.set_color_depth(ColorDepth::detect());
+        term.string_builder(0, 0, "x")
+            .fg(Color::Rgb(255, 0, 0))
+            .draw();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[38;2;255;0;0m"));
+    }
+
+    #[test]
+    fn theme_roles() {
+        let mut term = TermBuf::init().unwrap();
+        term.string_builder(0, 0, "e").role("error").draw();
+        assert_eq!(term.buffer.get(0, 0).unwrap().fg, Some(Color::Red));
+        assert_eq!(term.buffer.get(0, 0).unwrap().style, Style::Bold);
+
+        // Roles only replace what they set, and later settings win
+        term.string_builder(5, 0, "w")
+            .bg(Color::Blue)
+            .style(Style::Italic)
+            .role("warning")
+            .draw();
+        assert_eq!(term.buffer.get(5, 0).unwrap().fg, Some(Color::Yellow));
+        assert_eq!(term.buffer.get(5, 0).unwrap().bg, Some(Color::Blue));
+        assert_eq!(term.buffer.get(5, 0).unwrap().style, Style::Italic);
+        term.string_builder(6, 0, "t")
+            .role("error")
+            .fg(Color::Green)
+            .draw();
+        assert_eq!(term.buffer.get(6, 0).unwrap().fg, Some(Color::Green));
+        assert_eq!(term.buffer.get(6, 0).unwrap().style, Style::Bold);
+
+        // Cell builders take roles from the theme of the buffer that made them
+        let cell = term.cell_builder('x').role("error").build();
+        assert_eq!(cell.fg, Some(Color::Red));
+        assert_eq!(CellBuilder::new('x').role("error").build().fg, None);
+
+        let theme: Theme = r##"
+            border = { fg = "light-black", bg = "ansi(235)" }
+
+            [error]
+            fg = "#ff0000" # pure red
+            style = "italic, underline"
+        "##
+        .parse()
+        .unwrap();
+        term.set_theme(theme);
+        term.string_builder(0, 1, "e").role("error").draw();
+        term.box_builder(2, 2, 1, 1).role("border").draw();
+        assert_eq!(term.buffer.get(0, 0).unwrap().fg, Some(Color::Red));
+        assert_eq!(
+            term.buffer.get(0, 1).unwrap().fg,
+            Some(Color::Rgb(255, 0, 0))
+        );
+        assert_eq!(
+            term.buffer.get(0, 1).unwrap().style,
+            Style::Italic | Style::Underline
+        );
+        assert_eq!(
+            term.theme().get("border"),
+            Some(&ThemeStyle {
+                fg: Some(Color::LightBlack),
+                bg: Some(Color::AnsiValue(235)),
+                style: None,
+            })
+        );
+        assert_eq!(
+            term.buffer.get(2, 2).unwrap().bg,
+            Some(Color::AnsiValue(235))
+        );
+    }
+
+    #[test]
+    fn extended_styles() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.set_capabilities(Capabilities {
+            styled_underlines: true,
+            ..Capabilities::default()
+        });
+        term.string_builder(0, 0, "x")
+            .style(Style::Bold | Style::CurlyUnderline | Style::Overline)
+            .draw();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[1;4:3;53m"));
+
+        term.terminal.clear();
+        term.set_capabilities(Capabilities::default());
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[1;4;53m"));
+    }
+
+    #[test]
+    fn underline_color() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.set_capabilities(Capabilities {
+            underline_color: true,
+            ..Capabilities::default()
+        });
+        term.string_builder(0, 0, "typo")
+            .style(Style::CurlyUnderline)
+            .underline_color(Color::Red)
+            .draw();
+        assert_eq!(
+            term.buffer.get(0, 0).unwrap().underline_color,
+            Some(Color::Red)
+        );
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[58;5;1m"));
+        assert_eq!(out.matches("\x1b[58;5;1m").count(), 1);
+
+        // Changing only the underline color redraws the line
+        term.terminal.clear();
+        term.set_cell_underline_color(0, 0, Color::Blue);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[58;5;4m"));
+
+        term.terminal.clear();
+        term.set_capabilities(Capabilities::default());
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[58;"));
+    }
+
+    #[test]
+    fn hyperlinks() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.set_capabilities(Capabilities {
+            hyperlinks: true,
+            ..Capabilities::default()
+        });
+        term.print(0, 0, "see ");
+        term.string_builder(4, 0, "docs")
+            .link("https://example.com")
+            .draw();
+        let id = term.buffer.get(4, 0).unwrap().link.unwrap();
+        assert_eq!(term.buffer.get(7, 0).unwrap().link, Some(id));
+        assert_eq!(term.buffer.get(3, 0).unwrap().link, None);
+        assert_eq!(term.intern_link("https://example.com"), id);
+        assert_eq!(term.link_target(id).as_deref(), Some("https://example.com"));
+
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("see \x1b]8;id=1;https://example.com\x1b\\docs\x1b]8;;\x1b\\"));
+
+        // Removing the link redraws the line without it
+        term.terminal.clear();
+        term.set_cell_link(4, 0, None);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("see d\x1b]8;id=1;"));
+
+        // Compacting forgets the targets nothing links to, and redraws nothing
+        let unused = term.intern_link("https://unused.example");
+        term.string_builder(0, 1, "later")
+            .link("https://later.example")
+            .draw();
+        term.flush().unwrap();
+        let copy = term.buffer().clone();
+        term.terminal.clear();
+        term.compact_links();
+        term.flush().unwrap();
+        assert_eq!(term.frame_stats().cells_changed, 0);
+        let later = term.buffer.get(0, 1).unwrap().link.unwrap();
+        assert_eq!(
+            term.link_target(later).as_deref(),
+            Some("https://later.example")
+        );
+        // The id of the unused target was freed for the next one
+        assert_eq!(later, unused);
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+        // Clones keep their own ids, which are remapped when blitted back
+        term.blit(&copy, Rect::new(0, 1, 5, 1), 0, 2);
+        let id = term.buffer.get(0, 2).unwrap().link.unwrap();
+        assert_eq!(
+            term.link_target(id).as_deref(),
+            Some("https://later.example")
+        );
+    }
+
+    #[test]
+    fn patch_region() {
+        let mut term = TermBuf::init().unwrap();
+        term.string_builder(0, 0, "selected")
+            .fg(Color::Green)
+            .style(Style::Bold | Style::Italic)
+            .draw();
+        term.patch_region(
+            Rect::new(2, 0, 3, 1),
+            StylePatch::new()
+                .add(Style::Underline)
+                .remove(Style::Italic)
+                .bg(Color::Blue),
+        );
+        let cell = term.buffer.get(2, 0).unwrap();
+        assert_eq!(cell.content, 'l');
+        assert_eq!(cell.fg, Some(Color::Green));
+        assert_eq!(cell.bg, Some(Color::Blue));
+        assert_eq!(cell.style, Style::Bold | Style::Underline);
+        assert_eq!(
+            term.buffer.get(1, 0).unwrap().style,
+            Style::Bold | Style::Italic
+        );
+        assert_eq!(term.buffer.get(5, 0).unwrap().bg, None);
+
+        // Areas are clipped to the buffer
+        term.patch_region(
+            Rect::new(79, 24, usize::MAX, usize::MAX),
+            StylePatch::new().add(Style::Underline),
+        );
+        assert_eq!(term.buffer.get(79, 24).unwrap().style, Style::Underline);
+    }
+
+    #[test]
+    fn minimal_resets() {
+        let mut term = TermBuf::init_with_mode(RenderMode::Color).unwrap();
+        term.string_builder(0, 0, "ab")
+            .fg(Color::Red)
+            .style(Style::Bold)
+            .draw();
+        term.string_builder(2, 0, "c").fg(Color::Red).draw();
+        term.string_builder(3, 0, "d")
+            .style(Style::Italic)
+            .inherit_style()
+            .draw();
+        assert_eq!(term.buffer.get(3, 0).unwrap().style, Style::Italic);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("\x1b[38;5;1m\x1b[1mab\x1b[0m\x1b[38;5;1mc\x1b[39md "));
+        assert!(!out.contains("\x1b[3m"));
+    }
+
+    #[test]
+    fn border_styles() {
+        let mut term = TermBuf::init().unwrap();
+        term.box_builder(0, 0, 2, 1)
+            .border(BorderStyle::Rounded)
+            .draw();
+        assert_eq!(row(&term, 0, 4), "╭──╮");
+        assert_eq!(row(&term, 1, 4), "│  │");
+        assert_eq!(row(&term, 2, 4), "╰──╯");
+
+        term.clear().unwrap();
+        term.box_builder(0, 0, 2, 1)
+            .border(BorderStyle::Ascii)
+            .sides(Sides::Top | Sides::Bottom)
+            .draw();
+        assert_eq!(row(&term, 0, 4), "----");
+        assert_eq!(row(&term, 1, 4), "    ");
+        assert_eq!(row(&term, 2, 4), "----");
+
+        term.line_builder(0, 3, 2).border(BorderStyle::Heavy).draw();
+        assert_eq!(term.buffer.get(1, 3).unwrap().content, '━');
+    }
+
+    #[test]
+    fn box_titles() {
+        let mut term = TermBuf::init().unwrap();
+        term.box_builder(0, 0, 10, 1)
+            .title("Files")
+            .title_role("title")
+            .footer("3/9")
+            .footer_align(Align::Right)
+            .draw();
+        assert_eq!(row(&term, 0, 12), "┌─ Files ──┐");
+        assert_eq!(row(&term, 2, 12), "└──── 3/9 ─┘");
+        assert_eq!(term.buffer.get(3, 0).unwrap().style, Style::Bold);
+        assert_eq!(term.buffer.get(1, 0).unwrap().style, Style::empty());
+
+        term.box_builder(0, 0, 10, 1)
+            .title("A much longer title")
+            .title_align(Align::Center)
+            .draw();
+        assert_eq!(row(&term, 0, 12), "┌─ A muc… ─┐");
+
+        term.box_builder(0, 0, 10, 1)
+            .title("Mid")
+            .title_align(Align::Center)
+            .label_padding(0)
+            .draw();
+        assert_eq!(row(&term, 0, 12), "┌───Mid────┐");
+
+        // Labels are left out with their border
+        term.box_builder(0, 5, 10, 1)
+            .sides(Sides::Left | Sides::Right)
+            .title("Files")
+            .footer("3/9")
+            .draw();
+        for y in 5..8 {
+            assert_eq!(row(&term, y, 12), "│          │");
+        }
+    }
+
+    #[test]
+    fn junctions() {
+        let mut term = TermBuf::init().unwrap();
+        term.set_merge_junctions(true);
+        term.draw_box(0, 0, 2, 2);
+        term.draw_box(3, 0, 2, 2);
+        term.line_builder(0, 2, 7).border(BorderStyle::Heavy).draw();
+        assert_eq!(row(&term, 0, 7), "┌──┬──┐");
+        assert_eq!(row(&term, 1, 7), "│  │  │");
+        assert_eq!(row(&term, 2, 7), "┝━━┿━━┥");
+        assert_eq!(row(&term, 3, 7), "└──┴──┘");
+
+        // A line drawn before the box it crosses only sticks out where it continues
+        term.clear().unwrap();
+        term.line_builder(3, 0, 4).vertical().draw();
+        term.draw_box(0, 0, 5, 2);
+        assert_eq!(row(&term, 0, 7), "┌──┬──┐");
+        assert_eq!(row(&term, 3, 7), "└──┴──┘");
+
+        term.box_builder(0, 0, 2, 2)
+            .border(BorderStyle::Double)
+            .merge_junctions(false)
+            .draw();
+        assert_eq!(term.buffer.get(3, 0).unwrap().content, '╗');
+    }
+
+    #[test]
+    fn fill() {
+        let mut term = TermBuf::init().unwrap();
+        let mut builder = CellBuilder::new('.');
+        term.fill(Rect::new(1, 1, 3, 2), builder.bg(Color::Blue).build());
+        assert_eq!(term.buffer.get(1, 1).unwrap().content, '.');
+        assert_eq!(term.buffer.get(3, 2).unwrap().bg, Some(Color::Blue));
+        assert_eq!(term.buffer.get(1, 3).unwrap(), TermCell::empty());
+
+        term.clear_region(Rect::new(2, 1, 5, 1));
+        assert_eq!(term.buffer.get(1, 1).unwrap().content, '.');
+        assert_eq!(term.buffer.get(2, 1).unwrap(), TermCell::empty());
+
+        term.box_builder(0, 0, 2, 1).fill_bg(Color::Red).draw();
+        assert_eq!(term.buffer.get(1, 1).unwrap().bg, Some(Color::Red));
+        assert_eq!(term.buffer.get(1, 1).unwrap().content, ' ');
+        assert_eq!(term.buffer.get(3, 1).unwrap().content, '│');
+        assert_eq!(term.buffer.get(1, 0).unwrap().bg, None);
+    }
+
+    #[test]
+    fn fill_wide() {
+        let mut term = TermBuf::init().unwrap();
+        // Filling over either half of a wide character leaves the other half blank
+        term.print(0, 1, "漢x");
+        term.fill(Rect::new(1, 1, 2, 1), TermCell::with_char('.'));
+        assert_eq!(row(&term, 1, 3), " ..");
+        term.print(0, 1, "x漢x");
+        term.fill(Rect::new(0, 1, 2, 1), TermCell::with_char('.'));
+        assert_eq!(row(&term, 1, 4), ".. x");
+
+        term.print(0, 0, "abcdef");
+        term.fill(Rect::new(0, 0, 3, 1), TermCell::with_char('漢'));
+        let row: Vec<char> = (0..5)
+            .map(|x| term.buffer.get(x, 0).unwrap().content)
+            .collect();
+        assert_eq!(row, ['漢', ' ', ' ', 'd', 'e']);
+        assert_eq!(term.buffer.get(2, 0).unwrap().width, 1);
+
+        // The last wide character would cover the right border
+        term.box_builder(0, 2, 3, 1).fill_char('漢').draw();
+        let row: Vec<char> = (0..5)
+            .map(|x| term.buffer.get(x, 3).unwrap().content)
+            .collect();
+        assert_eq!(row, ['│', '漢', ' ', ' ', '│']);
+    }
+
+    #[test]
+    fn cut_wide() {
+        let mut term = TermBuf::init().unwrap();
+        let wide = TermCell {
+            bg: Some(Color::Red),
+            ..TermCell::with_char('漢')
+        };
+        let half = TermCell {
+            bg: Some(Color::Red),
+            ..TermCell::with_char(' ')
+        };
+        // Filling, blitting and the stats overlay leave the same halves behind
+        term.set_cell(0, 0, wide);
+        term.fill(Rect::new(1, 0, 1, 1), TermCell::with_char('.'));
+        assert_eq!(term.buffer.get(0, 0).unwrap(), half);
+
+        let dot = CellBuffer::new(1, 1);
+        term.set_cell(0, 1, wide);
+        term.blit(&dot, dot.area(), 0, 1);
+        assert_eq!(term.buffer.get(1, 1).unwrap(), half);
+
+        // The first overlay reads " 0 fps 0 B ", so starts in column 69
+        term.set_cell(68, 24, wide);
+        term.set_stats_overlay(Some(Corner::BottomRight));
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(68, 24).unwrap(), half);
+        assert_eq!(term.prev_buffer.get(69, 24).unwrap().content, ' ');
+    }
+
+    #[test]
+    fn popup_oversized() {
+        let mut term = TermBuf::init().unwrap();
+        term.popup_in(Rect::new(0, 0, 10, 5), 20, 1).draw();
+        assert_eq!(term.buffer.get(0, 1).unwrap().content, '┌');
+        assert_eq!(term.buffer.get(9, 1).unwrap().content, '┐');
+        assert_eq!(term.buffer.get(9, 3).unwrap().content, '┘');
+        // The shadow stays in the area
+        assert_eq!(term.buffer.get(10, 2).unwrap().style, Style::empty());
+        assert_eq!(term.buffer.get(5, 4).unwrap().style, Style::Faint);
+
+        // Areas too small for the border are left alone
+        term.popup_in(Rect::new(20, 0, 1, 5), 4, 4).draw();
+        term.popup_in(Rect::new(30, 0, 5, 1), 4, 4).draw();
+        for x in 19..36 {
+            for y in 0..6 {
+                assert_eq!(term.buffer.get(x, y).unwrap(), TermCell::empty());
+            }
+        }
+    }
+
+    #[test]
+    fn popup() {
+        let mut term = TermBuf::init().unwrap();
+        term.fill(term.area(), TermCell::with_char('x'));
+        term.popup(8, 3).title("Quit?").draw();
+        // 80x25 with a 10x5 box puts the corner at (35, 10)
+        assert_eq!(term.buffer.get(35, 10).unwrap().content, '┌');
+        assert_eq!(term.buffer.get(38, 10).unwrap().content, 'Q');
+        assert_eq!(term.buffer.get(36, 11).unwrap(), TermCell::empty());
+        assert_eq!(term.buffer.get(44, 14).unwrap().content, '┘');
+
+        // The shadow dims the cells without erasing them
+        let shadow = term.buffer.get(36, 15).unwrap();
+        assert_eq!(shadow.content, 'x');
+        assert_eq!(shadow.style, Style::Faint);
+        assert_eq!(term.buffer.get(45, 11).unwrap().style, Style::Faint);
+        assert_eq!(term.buffer.get(45, 10).unwrap().style, Style::empty());
+        assert_eq!(term.buffer.get(35, 15).unwrap().style, Style::empty());
+    }
+
+    #[test]
+    fn popup_on_layer() {
+        let mut term = TermBuf::init().unwrap();
+        term.fill(term.area(), TermCell::with_char('x'));
+        term.set_cell_style(36, 15, Style::Bold);
+        let area = term.area();
+        let modal = term.add_layer(area, 1);
+        term.draw_on(Some(modal));
+        term.popup(8, 3).draw();
+        term.flush().unwrap();
+
+        // The shadow dims the cells below the layer, keeping their own styles
+        let shadow = term.prev_buffer.get(36, 15).unwrap();
+        assert_eq!(shadow.content, 'x');
+        assert_eq!(shadow.style, Style::Faint | Style::Bold);
+        assert_eq!(term.prev_buffer.get(45, 11).unwrap().style, Style::Faint);
+        assert_eq!(term.prev_buffer.get(45, 10).unwrap().style, Style::empty());
+        assert_eq!(term.prev_buffer.get(36, 11).unwrap(), TermCell::empty());
+    }
+
+    #[test]
+    fn layers() {
+        let mut term = TermBuf::init().unwrap();
+        term.print(0, 0, "base text");
+        term.set_cell_bg(6, 0, Color::Blue);
+        let menu = term.add_layer(Rect::new(4, 0, 4, 1), 1);
+        term.draw_on(Some(menu));
+        term.string_builder(0, 0, "ab").fg(Color::Red).draw();
+        term.char_builder(2, 0, ' ').inherit_bg().draw();
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(4, 0).unwrap().content, 'a');
+        assert_eq!(term.prev_buffer.get(4, 0).unwrap().fg, Some(Color::Red));
+        assert_eq!(term.prev_buffer.get(6, 0).unwrap().bg, Some(Color::Blue));
+        // The base is untouched, and transparent cells show it
+        assert_eq!(term.buffer.get(4, 0).unwrap().content, ' ');
+        assert_eq!(term.prev_buffer.get(7, 0).unwrap().content, 'x');
+
+        // A higher layer covers the first, and removing layers restores the base
+        let tooltip = term.add_layer(Rect::new(0, 0, 1, 1), 2);
+        term.move_layer(tooltip, 6, 0);
+        term.draw_on(Some(tooltip));
+        term.char_builder(0, 0, '?').inherit_bg().draw();
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(6, 0).unwrap().content, '?');
+        assert_eq!(term.prev_buffer.get(6, 0).unwrap().bg, Some(Color::Blue));
+        term.set_layer_visible(tooltip, false);
+        term.remove_layer(menu);
+        term.terminal.clear();
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer[0], term.buffer.cells[0]);
+        assert!(!term.terminal.is_empty());
+
+        // Wide characters cut by a layer lose their right half
+        term.print(10, 1, "漢");
+        let cover = term.add_layer(Rect::new(11, 1, 1, 1), 0);
+        term.draw_on(Some(cover));
+        term.set_char(0, 0, '|');
+        term.draw_on(None);
+        term.flush().unwrap();
+        assert_eq!(
+            term.prev_buffer.get(10, 1).unwrap(),
+            TermCell::with_char(' ')
+        );
+        assert_eq!(term.prev_buffer.get(11, 1).unwrap().content, '|');
+
+        // And covering their left half leaves the right half blank
+        term.print(20, 1, "ab");
+        term.print(20, 1, "漢");
+        let cover = term.add_layer(Rect::new(20, 1, 1, 1), 0);
+        term.draw_on(Some(cover));
+        term.set_char(0, 0, '|');
+        term.draw_on(None);
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(20, 1).unwrap().content, '|');
+        assert_eq!(
+            term.prev_buffer.get(21, 1).unwrap(),
+            TermCell::with_char(' ')
+        );
+    }
+
+    #[test]
+    fn blit() {
+        let mut canvas = CellBuffer::new(6, 3);
+        canvas.print(0, 0, "a漢bcd");
+        canvas.print(0, 1, "line 2");
+        canvas
+            .string_builder(0, 2, "link")
+            .link("https://example.com")
+            .draw();
+
+        let mut term = TermBuf::init().unwrap();
+        term.print(0, 0, "xx漢xxxx");
+        // The source area starts on the right half of 漢, and ends on a wide character
+        term.blit(&canvas, Rect::new(2, 0, 3, 1), 3, 0);
+        assert_eq!(row(&term, 0, 8), "xx  bcxx");
+        assert_eq!(term.buffer.get(2, 0).unwrap().width, 1);
+
+        // Drawing over the left half of a wide character blanks its right half
+        term.print(0, 4, "abcdef");
+        term.print(2, 4, "漢");
+        let mut dot = CellBuffer::new(1, 1);
+        dot.set_char(0, 0, 'q');
+        term.blit(&dot, dot.area(), 2, 4);
+        assert_eq!(row(&term, 4, 6), "abq ef");
+
+        // Clipped to the screen, and links keep their targets in the other table
+        let other = term.intern_link("https://other.example");
+        term.blit(&canvas, canvas.area(), 78, 1);
+        assert_eq!(term.buffer.get(79, 1).unwrap(), TermCell::with_char(' '));
+        assert_eq!(term.buffer.get(79, 2).unwrap().content, 'i');
+        let link = term.buffer.get(78, 3).unwrap().link.unwrap();
+        assert_eq!(
+            term.link_target(link).as_deref(),
+            Some("https://example.com")
+        );
+        assert_ne!(link, canvas.get(0, 2).unwrap().link.unwrap());
+        assert_ne!(link, other);
+
+        // Transparent cells leave the destination showing
+        let mut sprite = CellBuffer::transparent(3, 1);
+        sprite.set_char(1, 0, '*');
+        term.blit(&sprite, sprite.area(), 0, 1);
+        assert_eq!(term.buffer.get(0, 1).unwrap(), TermCell::empty());
+        assert_eq!(term.buffer.get(1, 1).unwrap().content, '*');
+    }
+
+    #[test]
+    fn packed_cells() {
+        let mut term = TermBuf::init().unwrap();
+        let link = term.intern_link("https://example.com");
+        let cells = [
+            TermCell::empty(),
+            TermCell::transparent(),
+            TermCell::with_char('漢'),
+            TermCell {
+                fg: Some(Color::Rgb(1, 2, 3)),
+                bg: Some(Color::AnsiValue(200)),
+                underline_color: Some(Color::LightMagenta),
+                style: Style::Bold | Style::Encircled | Style::CurlyUnderline,
+                inherit: Inherit::Fg,
+                link: Some(link),
+                ..TermCell::with_char('\u{10FFFF}')
+            },
+            TermCell {
+                fg: Some(Color::LightWhite),
+                bg: Some(Color::Rgb(255, 255, 255)),
+                style: Style::all(),
+                inherit: Inherit::all(),
+                ..TermCell::with_char('x')
+            },
+        ];
+        for cell in &cells {
+            assert_eq!(PackedCell::new(cell).unpack(), *cell);
+        }
+    }
+
+    #[test]
+    fn grid_memory() {
+        // The cells of a 300x100 grid are packed into 20 bytes each
+        let buffer = CellBuffer::new(300, 100);
+        assert_eq!(buffer.cells_size(), 600_000);
+
+        // Rows are compared without unpacking
+        let mut other = buffer.clone();
+        other.set_char(0, 1, 'x');
+        assert!(buffer.same_row(&other, 0));
+        assert!(!buffer.same_row(&other, 1));
+        assert!(!buffer.same_row(&other, 100));
+    }
+
+    #[test]
+    fn dirty_rows() {
+        let mut term = TermBuf::init().unwrap();
+        term.cursor = false;
+        assert!(term.is_dirty());
+        term.flush().unwrap();
+        assert!(!term.is_dirty());
+
+        // Nothing is written when nothing was drawn
+        term.terminal.clear();
+        term.flush().unwrap();
+        assert!(term.terminal.is_empty());
+
+        // Only the rows drawn on are written
+        term.print(0, 3, "three");
+        term.string_builder(0, 5, "five").fg(Color::Red).draw();
+        assert!(term.is_dirty());
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("three") && out.contains("five"));
+        assert_eq!(out.matches("\x1b[").count(), 4);
+
+        // Layers make the rows they cover dirty when drawn on or moved
+        let layer = term.add_layer(Rect::new(0, 10, 2, 1), 1);
+        assert!(!term.is_dirty());
+        term.draw_on(Some(layer));
+        term.print(0, 0, "ab");
+        assert!(term.is_dirty());
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(0, 10).unwrap().content, 'a');
+        term.move_layer(layer, 0, 12);
+        assert!(term.is_dirty());
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer.get(0, 10).unwrap().content, ' ');
+        assert_eq!(term.prev_buffer.get(0, 12).unwrap().content, 'a');
+        assert!(!term.is_dirty());
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut term = TermBuf::init().unwrap();
+        term.cursor = false;
+        term.print(0, 0, "top");
+        term.print(0, 4, "left right");
+        term.flush().unwrap();
+
+        // The screen is cleared, then only the rows with content are drawn
+        term.terminal.clear();
+        term.invalidate();
+        assert!(term.is_dirty());
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[2J"));
+        assert!(out.contains("top") && out.contains("left right"));
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+
+        // Parts of rows are erased without moving the rest of the line
+        term.terminal.clear();
+        term.invalidate_region(Rect::new(5, 4, 100, 1));
+        term.invalidate_region(Rect::new(0, 30, 80, 1));
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[5;6H\x1b[75X"));
+        assert!(out.contains("left right"));
+        assert!(!out.contains("top"));
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+    }
+
+    #[test]
+    fn resize_buffer() {
+        let mut term = TermBuf::init().unwrap();
+        term.print(0, 0, "top");
+        term.flush().unwrap();
+
+        // The screen is cleared and drawn again at the new size
+        term.buffer_mut().resize(30, 8);
+        term.print(0, 7, "bottom");
+        term.terminal.clear();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[2J"));
+        assert!(out.contains("bottom"));
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+
+        // Regions are clamped to the new size
+        term.buffer_mut().resize(80, 25);
+        term.invalidate_region(Rect::new(20, 20, 40, 5));
+        term.flush().unwrap();
+        term.buffer_mut().resize(10, 4);
+        term.invalidate_region(Rect::new(20, 20, 40, 5));
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+    }
+
+    #[test]
+    fn synchronized_output() {
+        let mut term = TermBuf::init().unwrap();
+        term.set_capabilities(Capabilities {
+            synchronized_output: true,
+            ..Capabilities::default()
+        });
+        term.print(0, 0, "frame");
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[?2026h"));
+        assert!(out.ends_with("\x1b[?2026l"));
+        assert!(out.contains("frame"));
+
+        // Nothing to draw, so no update is started
+        term.terminal.clear();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[?2026"));
+
+        term.set_render_mode(RenderMode::Plain);
+        term.terminal.clear();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("frame\r\n"));
+        assert!(!out.contains('\x1b'));
+    }
+
+    #[test]
+    fn failed_write() {
+        let mut term = TermBuf::init().unwrap();
+        term.print(0, 0, "first");
+        term.print(0, 9, "second");
+        term.terminal.fail_after = Some(12);
+        assert!(term.flush().is_err());
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("second"));
+
+        // The part of the frame that didn't make it is written by the next flush
+        assert!(term.is_dirty());
+        term.terminal.clear();
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.contains("first") && out.contains("second"));
+    }
+
+    #[test]
+    fn frame_stats() {
+        let mut term = TermBuf::init().unwrap();
+        term.flush().unwrap();
+        term.terminal.clear();
+        term.print(0, 0, "hello");
+        term.print(0, 1, "hi");
+        let stats = term.flush().unwrap();
+        assert_eq!(stats.cells_changed, 7);
+        assert_eq!(stats.bytes, term.terminal.len());
+
+        term.terminal.clear();
+        let stats = term.flush().unwrap();
+        assert_eq!(stats.cells_changed, 0);
+        assert_eq!(stats.bytes, term.terminal.len());
+
+        term.set_render_mode(RenderMode::Plain);
+        term.print(1, 1, "o");
+        term.terminal.clear();
+        let stats = term.flush().unwrap();
+        assert_eq!(stats.cells_changed, 1);
+        assert_eq!(stats.bytes, term.terminal.len());
+        assert_eq!(stats.rows_examined, 25);
+        assert_eq!(stats.escape_sequences, 0);
+        assert_eq!(term.frame_stats(), stats);
+    }
+
+    #[test]
+    fn stats_overlay() {
+        let mut term = TermBuf::init().unwrap();
+        term.set_capabilities(Capabilities {
+            hyperlinks: true,
+            ..Capabilities::default()
+        });
+        term.cursor = false;
+        term.flush().unwrap();
+        term.string_builder(0, 2, "docs")
+            .fg(Color::Red)
+            .link("https://example.com")
+            .draw();
+        let stats = term.flush().unwrap();
+        assert_eq!(stats.rows_examined, 1);
+        // Goto, fg, link open, link close and fg reset
+        assert_eq!(stats.escape_sequences, 5);
+
+        term.set_stats_overlay(Some(Corner::BottomRight));
+        term.flush().unwrap();
+        let row: String = (66..80)
+            .map(|x| term.prev_buffer.get(x, 24).unwrap().content)
+            .collect();
+        assert!(row.ends_with(&format!("fps {} B ", stats.bytes)));
+        assert!(term
+            .prev_buffer
+            .get(79, 24)
+            .unwrap()
+            .style
+            .contains(Style::Invert));
+        // The buffer itself is untouched
+        assert_eq!(term.buffer.get(79, 24).unwrap(), TermCell::empty());
+
+        term.set_stats_overlay(None);
+        term.flush().unwrap();
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+    }
+
+    #[test]
+    fn scroll_region() {
+        let mut term = TermBuf::init().unwrap();
+        for y in 0..25 {
+            term.print(0, y, &format!("line {}", y));
+        }
+        term.flush().unwrap();
+        term.terminal.clear();
+
+        // The terminal scrolls the text, and only the new line is written
+        let area = term.area();
+        term.scroll_region(area, 1);
+        term.print(0, 24, "new line");
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[1;25r\x1b[25;1H\n\x1b[r"));
+        assert!(out.contains("new line"));
+        assert!(!out.contains("line 1"));
+        assert_eq!(term.buffer.get(5, 0).unwrap().content, '1');
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+
+        // Scrolling down uses reverse indexes at the top of the region
+        term.terminal.clear();
+        term.scroll_region(Rect::new(0, 2, 80, 3), -2);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(out.starts_with("\x1b[3;5r\x1b[3;1H\x1bM\x1bM\x1b[r"));
+        assert_eq!(term.buffer.get(5, 4).unwrap().content, '3');
+        assert_eq!(term.buffer.get(5, 2).unwrap(), TermCell::empty());
+
+        // Areas narrower than the screen are redrawn instead
+        term.terminal.clear();
+        term.scroll_region(Rect::new(0, 10, 40, 5), 1);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[r"));
+        assert!(out.contains("line 12"));
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+
+        // A region of one row is redrawn, scrolling it would scroll the whole screen
+        term.terminal.clear();
+        term.scroll_region(Rect::new(0, 24, 80, 1), 1);
+        term.scroll_region(Rect::new(0, 0, 80, 1), -1);
+        term.flush().unwrap();
+        let out = String::from_utf8(term.terminal.clone()).unwrap();
+        assert!(!out.contains("\x1b[r"));
+        assert!(!out.contains('\n'));
+        assert!(!out.contains("\x1bM"));
+        assert_eq!(term.buffer.get(0, 24).unwrap(), TermCell::empty());
+        assert_eq!(term.prev_buffer, term.buffer.cells);
+    }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::num::NonZeroU32;
+use std::sync::{Mutex, MutexGuard};
+
+use grid::Grid;
+
+/// Refers to an interned hyperlink target
+///
+/// Create one with [`intern_link`][::CellBuffer::intern_link], or use
+/// [`StyleCellBuilder::link`][::builder::StyleCellBuilder::link]. Ids belong to the buffer that
+/// made them and the layers of its `TermBuf`, cells blitted from another buffer or a clone get
+/// new ids. [`compact_links`][::TermBuf::compact_links] gives every link a new id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LinkId(NonZeroU32);
+
+impl LinkId {
+    /// Returns the id as a number, which is never zero
+    pub(crate) fn to_u32(self) -> u32 {
+        self.0.get()
+    }
+
+    /// Returns the id with a number from `to_u32`, or `None` for zero
+    pub(crate) fn from_u32(id: u32) -> Option<LinkId> {
+        NonZeroU32::new(id).map(LinkId)
+    }
+}
+
+/// Stores each hyperlink target once, so cells only carry a small id
+///
+/// A `TermBuf` shares one table between its buffers, so ids mean the same in all of them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LinkTable {
+    targets: Vec<String>,
+    ids: HashMap<String, LinkId>,
+}
+
+impl LinkTable {
+    /// Returns the id of a target, adding it if needed
+    pub(crate) fn intern(&mut self, target: &str) -> LinkId {
+        if let Some(id) = self.ids.get(target) {
+            return *id;
+        }
+        self.targets.push(target.to_owned());
+        let id = LinkId(NonZeroU32::new(self.targets.len() as u32).expect("ids start at one"));
+        self.ids.insert(target.to_owned(), id);
+        id
+    }
+
+    /// Returns the target of an id
+    pub(crate) fn get(&self, id: LinkId) -> Option<&str> {
+        self.targets
+            .get(id.0.get() as usize - 1)
+            .map(|target| target.as_str())
+    }
+
+    /// Drops the targets no cell of `grids` links to, giving the rest new ids
+    ///
+    /// The grids must be every grid using the table, or their ids would point at other targets
+    pub(crate) fn compact(&mut self, grids: &mut [&mut Grid]) {
+        let old = mem::take(self);
+        let mut ids = HashMap::new();
+        for grid in grids.iter_mut() {
+            for y in 0..grid.height() {
+                // Rows without links are left alone, so they are not marked dirty
+                if grid[y].iter().all(|cell| cell.link().is_none()) {
+                    continue;
+                }
+                for cell in grid[y].iter_mut() {
+                    if let Some(id) = cell.link() {
+                        let new_id = *ids
+                            .entry(id)
+                            .or_insert_with(|| old.get(id).map(|target| self.intern(target)));
+                        *cell = cell.with_link(new_id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Locks a shared link table
+///
+/// The table is always left consistent, so a panic while it was locked does not matter
+pub(crate) fn lock(links: &Mutex<LinkTable>) -> MutexGuard<'_, LinkTable> {
+    links.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Writes the OSC 8 sequence starting a hyperlink
+///
+/// The id lets terminals treat runs of the same link on separate lines as one link. Bytes of the
+/// target outside printable ascii are percent-encoded, so a target can't end the sequence early
+/// and inject its own
+pub(crate) fn write_open<W: Write>(out: &mut W, target: &str, id: LinkId) -> io::Result<()> {
+    write!(out, "\x1b]8;id={};", id.0)?;
+    for byte in target.bytes() {
+        if (0x20..=0x7e).contains(&byte) {
+            out.write_all(&[byte])?;
+        } else {
+            write!(out, "%{:02X}", byte)?;
+        }
+    }
+    out.write_all(b"\x1b\\")
+}
+
+/// The OSC 8 sequence ending a hyperlink
+pub(crate) const CLOSE: &str = "\x1b]8;;\x1b\\";
+
+#[cfg(test)]
+mod test {
+    use super::{write_open, LinkId, LinkTable};
+    use grid::Grid;
+    use TermCell;
+
+    fn open(target: &str, id: LinkId) -> String {
+        let mut out = Vec::new();
+        write_open(&mut out, target, id).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn open_escapes_target() {
+        let id = LinkId::from_u32(3).unwrap();
+        assert_eq!(
+            open("https://example.com/a b", id),
+            "\x1b]8;id=3;https://example.com/a b\x1b\\"
+        );
+        assert_eq!(
+            open("file:///tmp/a\x1b]0;pwned\x07", id),
+            "\x1b]8;id=3;file:///tmp/a%1B]0;pwned%07\x1b\\"
+        );
+        assert_eq!(open("/tmp/é", id), "\x1b]8;id=3;/tmp/%C3%A9\x1b\\");
+    }
+
+    #[test]
+    fn compact() {
+        let mut table = LinkTable::default();
+        let unused = table.intern("https://unused.example");
+        let used = table.intern("https://used.example");
+        let mut grid = Grid::new(2, 1, TermCell::empty());
+        grid.set(
+            1,
+            0,
+            TermCell {
+                link: Some(used),
+                ..TermCell::with_char('a')
+            },
+        );
+        table.compact(&mut [&mut grid]);
+        let id = grid.get(1, 0).unwrap().link.unwrap();
+        assert_eq!(table.get(id), Some("https://used.example"));
+        assert_eq!(table.targets.len(), 1);
+        assert_ne!(table.intern("https://unused.example"), unused);
+    }
+}
@@ -0,0 +1,152 @@
+use std::env;
+use {Color, Style, TermCell};
+
+/// Controls which escape sequences are emitted when flushing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Colors and styles are emitted
+    Color,
+    /// Colors are dropped, styles are kept
+    ///
+    /// Cells that only stand out by their background, such as selected items, are drawn inverted
+    Monochrome,
+    /// No escape sequences are emitted, only the text of each line followed by a newline
+    Plain,
+}
+
+impl RenderMode {
+    /// Picks a mode from the environment
+    ///
+    /// `TERM=dumb` selects `Plain`, a non-empty `NO_COLOR` selects `Monochrome`
+    pub fn detect() -> RenderMode {
+        if env::var("TERM").map(|term| term == "dumb").unwrap_or(false) {
+            RenderMode::Plain
+        } else if env::var_os("NO_COLOR")
+            .map(|v| !v.is_empty())
+            .unwrap_or(false)
+        {
+            RenderMode::Monochrome
+        } else {
+            RenderMode::Color
+        }
+    }
+
+    /// Returns a cell with the colors and style as they should be drawn in this mode
+    pub(crate) fn apply(self, cell: &TermCell) -> TermCell {
+        match self {
+            RenderMode::Color => *cell,
+            RenderMode::Monochrome => {
+                let mut style = cell.style;
+                if cell.bg.is_some() {
+                    // Keep highlighted cells distinct from their surroundings
+                    style |= if style.contains(Style::Invert) {
+                        Style::Bold
+                    } else {
+                        Style::Invert
+                    };
+                }
+                TermCell {
+                    fg: None,
+                    bg: None,
+                    underline_color: None,
+                    style,
+                    ..*cell
+                }
+            }
+            RenderMode::Plain => TermCell {
+                fg: None,
+                bg: None,
+                underline_color: None,
+                style: Style::empty(),
+                link: None,
+                ..*cell
+            },
+        }
+    }
+}
+
+/// The range of colors the terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// Any rgb color can be shown
+    TrueColor,
+    /// Only the 256 color palette can be shown, rgb colors are replaced by the closest match
+    Ansi256,
+}
+
+impl ColorDepth {
+    /// Picks a depth from the environment
+    ///
+    /// Rgb colors are written as they are unless the terminal is known to lack truecolor, like
+    /// Apple's Terminal, and `COLORTERM` doesn't say otherwise. Many terminals that support
+    /// truecolor don't set `COLORTERM`, so its absence alone doesn't select `Ansi256`
+    pub fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if colorterm != "truecolor" && colorterm != "24bit" && program == "Apple_Terminal" {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::TrueColor
+        }
+    }
+
+    /// Converts a color to one the terminal can show
+    pub(crate) fn convert(self, color: Color) -> Color {
+        match self {
+            ColorDepth::TrueColor => color,
+            ColorDepth::Ansi256 => color.to_ansi256(),
+        }
+    }
+}
+
+/// Optional terminal features, used to fall back when a feature is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Double, curly, dotted and dashed underlines, otherwise a plain underline is drawn
+    pub styled_underlines: bool,
+    /// Underline colors, otherwise underlines use the forground color
+    pub underline_color: bool,
+    /// OSC 8 hyperlinks, otherwise links are left out and only the text is drawn
+    pub hyperlinks: bool,
+    /// Synchronized output (DEC mode 2026), so each flush appears at once instead of partly
+    /// drawn frames showing
+    pub synchronized_output: bool,
+}
+
+impl Capabilities {
+    /// Guesses the capabilities from the environment
+    ///
+    /// Only terminals known to support a feature get it, anything else falls back
+    pub fn detect() -> Capabilities {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        let modern = term.contains("kitty")
+            || term.contains("foot")
+            || term.contains("wezterm")
+            || program == "WezTerm"
+            || program == "iTerm.app"
+            || program == "vscode"
+            || env::var_os("VTE_VERSION").is_some();
+        let synchronized = term.contains("kitty")
+            || term.contains("foot")
+            || term.contains("wezterm")
+            || term.contains("alacritty")
+            || program == "WezTerm"
+            || program == "iTerm.app";
+        Capabilities {
+            styled_underlines: modern,
+            underline_color: modern,
+            hyperlinks: modern,
+            synchronized_output: synchronized,
+        }
+    }
+
+    /// Converts a style to one the terminal can show
+    pub(crate) fn style(self, style: Style) -> Style {
+        if self.styled_underlines {
+            style
+        } else {
+            style.plain_underlines()
+        }
+    }
+}
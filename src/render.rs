@@ -0,0 +1,131 @@
+use std::io::{Error, Write};
+use termion::clear;
+use termion::color::{Bg, Fg, Reset};
+use termion::cursor::Goto;
+use {
+    Capabilities, Color, ColorDepth, Inherit, Rect, Style, TermCell, UnderlineColor,
+    UnderlineColorReset,
+};
+
+/// Starts a synchronized update, the terminal holds the screen until it ends (DEC mode 2026)
+pub(crate) const BEGIN_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026h";
+/// Ends a synchronized update, showing everything written since it began
+pub(crate) const END_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026l";
+
+/// The attributes the terminal is currently drawing with
+///
+/// Tracking these lets `flush` write only what changes between cells, and reset only when needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Pen {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    underline_color: Option<Color>,
+    style: Style,
+}
+
+impl Pen {
+    /// Returns the pen needed to draw a cell, converted to what the terminal can show
+    pub(crate) fn new(cell: &TermCell, depth: ColorDepth, caps: Capabilities) -> Pen {
+        let style = if cell.inherit.contains(Inherit::Style) {
+            Style::empty()
+        } else {
+            caps.style(cell.style)
+        };
+        let fg = cell.fg.filter(|_| !cell.inherit.contains(Inherit::Fg));
+        let bg = cell.bg.filter(|_| !cell.inherit.contains(Inherit::Bg));
+        Pen {
+            fg: fg.map(|c| depth.convert(c)),
+            bg: bg.map(|c| depth.convert(c)),
+            underline_color: cell
+                .underline_color
+                .filter(|_| caps.underline_color)
+                .map(|c| depth.convert(c)),
+            style,
+        }
+    }
+
+    /// Writes the escape sequences to switch to another pen
+    ///
+    /// Styles can only be turned off all at once, so removing any style resets everything first
+    pub(crate) fn change<W: Write>(&mut self, out: &mut W, next: Pen) -> Result<(), Error> {
+        if *self == next {
+            return Ok(());
+        }
+        if !(self.style - next.style).is_empty() {
+            write!(out, "\x1b[0m")?;
+            *self = Pen::default();
+        }
+        if self.fg != next.fg {
+            match next.fg {
+                Some(fg) => write!(out, "{}", Fg(fg))?,
+                None => write!(out, "{}", Fg(Reset))?,
+            }
+        }
+        if self.bg != next.bg {
+            match next.bg {
+                Some(bg) => write!(out, "{}", Bg(bg))?,
+                None => write!(out, "{}", Bg(Reset))?,
+            }
+        }
+        if self.underline_color != next.underline_color {
+            match next.underline_color {
+                Some(color) => write!(out, "{}", UnderlineColor(color))?,
+                None => write!(out, "{}", UnderlineColorReset)?,
+            }
+        }
+        write!(out, "{}", next.style - self.style)?;
+        *self = next;
+        Ok(())
+    }
+}
+
+/// A change the terminal makes to the screen itself on the next flush, before rows are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScreenOp {
+    /// Moves the rows from `top` up to `bottom` up by `n`, or down if `n` is negative
+    Scroll { top: usize, bottom: usize, n: isize },
+    /// Clears an area
+    Clear(Rect),
+    /// Clears the whole screen
+    ClearAll,
+}
+
+impl ScreenOp {
+    /// Writes the escape sequences for the change
+    ///
+    /// To scroll, the scroll region is limited to the rows with DECSTBM, then line feeds at its
+    /// bottom move the text up, or reverse indexes at its top move it down
+    pub(crate) fn write<W: Write>(self, out: &mut W, screen_width: usize) -> Result<(), Error> {
+        match self {
+            ScreenOp::Scroll { top, bottom, n } => {
+                write!(out, "\x1b[{};{}r", top + 1, bottom)?;
+                let count = n.unsigned_abs().min(bottom - top);
+                if n > 0 {
+                    write!(out, "{}", Goto(1, bottom as u16))?;
+                    for _ in 0..count {
+                        out.write_all(b"\n")?;
+                    }
+                } else {
+                    write!(out, "{}", Goto(1, top as u16 + 1))?;
+                    for _ in 0..count {
+                        out.write_all(b"\x1bM")?;
+                    }
+                }
+                write!(out, "\x1b[r")
+            }
+            ScreenOp::Clear(rect) => {
+                for y in rect.y..rect.y + rect.height {
+                    write!(out, "{}", Goto(rect.x as u16 + 1, y as u16 + 1))?;
+                    if rect.x == 0 && rect.width >= screen_width {
+                        write!(out, "{}", clear::CurrentLine)?;
+                    } else {
+                        // Erases characters without moving the rest of the line
+                        write!(out, "\x1b[{}X", rect.width)?;
+                    }
+                }
+                Ok(())
+            }
+            ScreenOp::ClearAll => write!(out, "{}", clear::All),
+        }
+    }
+}
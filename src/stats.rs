@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::time::Duration;
+
+use grid::Grid;
+use {Style, TermCell};
+
+/// What a [`flush`][::TermBuf::flush] wrote, for monitoring performance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameStats {
+    /// Rows compared with what the terminal showed, rows nothing was drawn on are skipped
+    pub rows_examined: usize,
+    /// Cells that differed from what the terminal showed
+    pub cells_changed: usize,
+    /// Bytes written to the terminal
+    pub bytes: usize,
+    /// Escape sequences written to the terminal
+    pub escape_sequences: usize,
+    /// Time spent flushing, including the write to the terminal
+    pub duration: Duration,
+}
+
+/// A corner of the screen, see [`set_stats_overlay`][::TermBuf::set_stats_overlay]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    /// The top left corner
+    TopLeft,
+    /// The top right corner
+    TopRight,
+    /// The bottom left corner
+    BottomLeft,
+    /// The bottom right corner
+    BottomRight,
+}
+
+impl Corner {
+    /// Returns the row of the corner on a screen of the given height
+    pub(crate) fn row(self, height: usize) -> usize {
+        match self {
+            Corner::TopLeft | Corner::TopRight => 0,
+            Corner::BottomLeft | Corner::BottomRight => height.saturating_sub(1),
+        }
+    }
+}
+
+/// Counts the escape sequences in some output
+///
+/// The string terminator closing an OSC sequence also starts with an escape, so is skipped
+pub(crate) fn count_escapes(out: &[u8]) -> usize {
+    out.iter()
+        .enumerate()
+        .filter(|&(i, &byte)| byte == 0x1b && out.get(i + 1) != Some(&b'\\'))
+        .count()
+}
+
+/// Draws the frame rate and the bytes written by the last flush in a corner of a frame
+///
+/// The text is formatted on the stack and written straight into the frame, so the overlay does
+/// not allocate in the frames it measures
+pub(crate) fn draw_overlay(frame: &mut Grid, corner: Corner, fps: f64, last: FrameStats) {
+    let mut text = [0u8; 48];
+    let capacity = text.len();
+    let len = {
+        let mut rest = &mut text[..];
+        // Only fails when the text does not fit, which cuts it short
+        let _ = write!(rest, " {:.0} fps {} B ", fps, last.bytes);
+        capacity - rest.len()
+    };
+    let width = len.min(frame.width());
+    let x = match corner {
+        Corner::TopLeft | Corner::BottomLeft => 0,
+        Corner::TopRight | Corner::BottomRight => frame.width() - width,
+    };
+    let y = corner.row(frame.height());
+    // Wide characters cut in half by the overlay are replaced with spaces
+    frame.split_wide_at(y, x);
+    frame.split_wide_at(y, x + width);
+    for (i, &byte) in text[..width].iter().enumerate() {
+        let cell = TermCell {
+            style: Style::Invert,
+            ..TermCell::with_char(byte as char)
+        };
+        frame.set(x + i, y, cell);
+    }
+}
@@ -1,32 +1,92 @@
-#![allow(unknown_lints, non_upper_case_globals, unreadable_literal)]
+#![allow(unknown_lints, non_upper_case_globals, clippy::unreadable_literal)]
+use std::error::Error;
 use std::fmt;
-use termion::style;
+use std::str::FromStr;
+use {Color, TermCell};
 
 bitflags! {
     /// Represents the style of a cell, not all terminals support all of these styles
     ///
-    /// See the [termion docs](::termion::style) for details
+    /// Extended underlines fall back to `Underline` on terminals without support for them,
+    /// see [`Capabilities`][::Capabilities]
     #[derive(Default)]
-    pub struct Style: u16 {
-        const Blink =      0b00000001;
-        const Bold =       0b00000010;
-        const CrossedOut = 0b00000100;
-        const Faint =      0b00001000;
-        const Framed =     0b00010000;
-        const Invert =     0b00100000;
-        const Italic =     0b01000000;
-        const Underline =  0b10000000;
-        const Reset =     0b100000000;
+    pub struct Style: u32 {
+        const Blink =           0b00000000000000001;
+        const Bold =            0b00000000000000010;
+        const CrossedOut =      0b00000000000000100;
+        const Faint =           0b00000000000001000;
+        const Framed =          0b00000000000010000;
+        const Invert =          0b00000000000100000;
+        const Italic =          0b00000000001000000;
+        const Underline =       0b00000000010000000;
+        const DoubleUnderline = 0b00000001000000000;
+        const CurlyUnderline =  0b00000010000000000;
+        const DottedUnderline = 0b00000100000000000;
+        const DashedUnderline = 0b00001000000000000;
+        const Overline =        0b00010000000000000;
+        const Conceal =         0b00100000000000000;
+        const RapidBlink =      0b01000000000000000;
+        const Encircled =       0b10000000000000000;
+    }
+}
+
+bitflags! {
+    /// Attributes of a cell that are taken from whatever is below it instead of the cell itself
+    ///
+    /// Cells drawn directly to the terminal have nothing below them, so inherited attributes use
+    /// the terminal's defaults. Styles set on a cell that inherits `Style` are added to the ones
+    /// below it
+    #[derive(Default)]
+    pub struct Inherit: u8 {
+        const Style =   0b0001;
+        const Content = 0b0010;
+        const Fg =      0b0100;
+        const Bg =      0b1000;
+    }
+}
+
+impl Style {
+    /// No styles, the same as `Style::empty()`
+    ///
+    /// This used to be a flag that reset all styles when drawn, cells now always start from the
+    /// default style, so it is no longer needed
+    #[deprecated(
+        note = "cells without styles are drawn with the default style, use `Style::empty()`"
+    )]
+    pub const Reset: Style = Style::empty();
+
+    /// All the underline variants other than the plain `Underline`
+    pub const EXTENDED_UNDERLINES: Style = Style::from_bits_truncate(
+        Style::DoubleUnderline.bits
+            | Style::CurlyUnderline.bits
+            | Style::DottedUnderline.bits
+            | Style::DashedUnderline.bits,
+    );
+
+    /// Replaces extended underlines with a plain `Underline`
+    pub fn plain_underlines(self) -> Style {
+        if self.intersects(Style::EXTENDED_UNDERLINES) {
+            (self - Style::EXTENDED_UNDERLINES) | Style::Underline
+        } else {
+            self
+        }
     }
 }
 
 macro_rules! impl_display_match {
-    ($($item:ident),*) => {
+    ($($item:ident => $code:expr),*) => {
         impl fmt::Display for Style {
+            /// Writes the SGR escape sequence enabling every style, nothing if there are none
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let mut first = true;
                 $(if self.contains(Style::$item) {
-                    style::$item.fmt(f)?;
+                    f.write_str(if first { "\x1b[" } else { ";" })?;
+                    f.write_str($code)?;
+                    first = false;
                 })*
+                if !first {
+                    f.write_str("m")?;
+                }
                 Ok(())
             }
         }
@@ -34,13 +94,140 @@ macro_rules! impl_display_match {
 }
 
 impl_display_match! {
-    Blink,
-    Bold,
-    CrossedOut,
-    Faint,
-    Framed,
-    Invert,
-    Italic,
-    Underline,
-    Reset
+    Bold => "1",
+    Faint => "2",
+    Italic => "3",
+    Underline => "4",
+    DoubleUnderline => "4:2",
+    CurlyUnderline => "4:3",
+    DottedUnderline => "4:4",
+    DashedUnderline => "4:5",
+    Blink => "5",
+    RapidBlink => "6",
+    Invert => "7",
+    Conceal => "8",
+    CrossedOut => "9",
+    Framed => "51",
+    Encircled => "52",
+    Overline => "53"
+}
+
+/// The names of the styles, as used by `FromStr`
+const NAMES: &[(&str, Style)] = &[
+    ("blink", Style::Blink),
+    ("bold", Style::Bold),
+    ("crossed-out", Style::CrossedOut),
+    ("faint", Style::Faint),
+    ("framed", Style::Framed),
+    ("invert", Style::Invert),
+    ("italic", Style::Italic),
+    ("underline", Style::Underline),
+    ("double-underline", Style::DoubleUnderline),
+    ("curly-underline", Style::CurlyUnderline),
+    ("dotted-underline", Style::DottedUnderline),
+    ("dashed-underline", Style::DashedUnderline),
+    ("overline", Style::Overline),
+    ("conceal", Style::Conceal),
+    ("hidden", Style::Conceal),
+    ("rapid-blink", Style::RapidBlink),
+    ("encircled", Style::Encircled),
+];
+
+/// An error returned when parsing a `Style` fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseStyleError {
+    input: String,
+}
+
+impl fmt::Display for ParseStyleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid style: `{}`", self.input)
+    }
+}
+
+impl Error for ParseStyleError {}
+
+impl FromStr for Style {
+    type Err = ParseStyleError;
+
+    /// Parses a set of styles from names separated by commas, `|`, `+` or spaces
+    ///
+    /// For example `bold, underline` or `crossed-out|italic`, an empty string is no style
+    fn from_str(s: &str) -> Result<Style, ParseStyleError> {
+        let mut style = Style::empty();
+        for name in s
+            .split(|c: char| c == ',' || c == '|' || c == '+' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+        {
+            let name = name.to_ascii_lowercase().replace('_', "-");
+            match NAMES
+                .iter()
+                .find(|(n, _)| *n == name || n.replace('-', "") == name)
+            {
+                Some((_, flag)) => style |= *flag,
+                None => return Err(ParseStyleError { input: name }),
+            }
+        }
+        Ok(style)
+    }
+}
+
+/// A change to apply to existing cells: styles to add and remove, and optional new colors
+///
+/// Apply a patch with [`CellBuffer::patch_region`][::CellBuffer::patch_region], for example to
+/// highlight a selection without redrawing its text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StylePatch {
+    /// Styles to add
+    pub add: Style,
+    /// Styles to remove, applied after `add`
+    pub remove: Style,
+    /// The new forground color, if it should change
+    pub fg: Option<Color>,
+    /// The new background color, if it should change
+    pub bg: Option<Color>,
+}
+
+impl StylePatch {
+    /// Creates a patch that changes nothing
+    pub fn new() -> StylePatch {
+        StylePatch::default()
+    }
+
+    /// Adds styles
+    pub fn add(&mut self, style: Style) -> &mut StylePatch {
+        self.add |= style;
+        self.remove -= style;
+        self
+    }
+
+    /// Removes styles
+    pub fn remove(&mut self, style: Style) -> &mut StylePatch {
+        self.remove |= style;
+        self.add -= style;
+        self
+    }
+
+    /// Replaces the forground color
+    pub fn fg(&mut self, color: Color) -> &mut StylePatch {
+        self.fg = Some(color);
+        self
+    }
+
+    /// Replaces the background color
+    pub fn bg(&mut self, color: Color) -> &mut StylePatch {
+        self.bg = Some(color);
+        self
+    }
+
+    /// Applies the patch to a single cell
+    pub fn apply(&self, cell: &mut TermCell) {
+        cell.style = (cell.style | self.add) - self.remove;
+        if self.fg.is_some() {
+            cell.fg = self.fg;
+        }
+        if self.bg.is_some() {
+            cell.bg = self.bg;
+        }
+    }
 }
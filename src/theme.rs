@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use {Color, Style};
+
+/// The colors and style of a theme role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ThemeStyle {
+    /// The forground color, if any
+    pub fg: Option<Color>,
+    /// The background color, if any
+    pub bg: Option<Color>,
+    /// The styles, if any
+    pub style: Option<Style>,
+}
+
+/// A set of named style roles, such as `"border"`, `"title"`, `"selection"`, or `"error"`
+///
+/// Builders accept a role with `role`, see [`CellBuffer::set_theme`][::CellBuffer::set_theme]
+///
+/// Themes can be parsed from a small subset of TOML, with an inline table or a table per role:
+///
+/// ```text
+/// # Comments start with a hash
+/// error = { fg = "light-red", style = "bold" }
+///
+/// [title]
+/// fg = "white"
+/// bg = "#1c1c1c"
+/// style = "bold, underline"
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Theme {
+    roles: HashMap<String, ThemeStyle>,
+}
+
+impl Theme {
+    /// Creates an empty theme
+    pub fn new() -> Theme {
+        Theme::default()
+    }
+
+    /// Creates a theme with a few common roles
+    ///
+    /// Defines `border`, `title`, `selection`, `error`, `warning`, and `dim`
+    pub fn basic() -> Theme {
+        let mut theme = Theme::new();
+        theme
+            .set(
+                "title",
+                ThemeStyle {
+                    style: Some(Style::Bold),
+                    ..ThemeStyle::default()
+                },
+            )
+            .set("border", ThemeStyle::default())
+            .set(
+                "selection",
+                ThemeStyle {
+                    style: Some(Style::Invert),
+                    ..ThemeStyle::default()
+                },
+            )
+            .set(
+                "error",
+                ThemeStyle {
+                    fg: Some(Color::Red),
+                    style: Some(Style::Bold),
+                    ..ThemeStyle::default()
+                },
+            )
+            .set(
+                "warning",
+                ThemeStyle {
+                    fg: Some(Color::Yellow),
+                    ..ThemeStyle::default()
+                },
+            )
+            .set(
+                "dim",
+                ThemeStyle {
+                    style: Some(Style::Faint),
+                    ..ThemeStyle::default()
+                },
+            );
+        theme
+    }
+
+    /// Reads a theme from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Theme, io::Error> {
+        let text = fs::read_to_string(path)?;
+        text.parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Sets the style of a role
+    pub fn set(&mut self, role: &str, style: ThemeStyle) -> &mut Theme {
+        self.roles.insert(role.to_owned(), style);
+        self
+    }
+
+    /// Gets the style of a role
+    pub fn get(&self, role: &str) -> Option<&ThemeStyle> {
+        self.roles.get(role)
+    }
+
+    /// Removes a role
+    pub fn remove(&mut self, role: &str) -> Option<ThemeStyle> {
+        self.roles.remove(role)
+    }
+
+    /// Iterates over all roles and their styles
+    pub fn roles(&self) -> impl Iterator<Item = (&str, &ThemeStyle)> {
+        self.roles
+            .iter()
+            .map(|(role, style)| (role.as_str(), style))
+    }
+}
+
+/// An error returned when parsing a `Theme` fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseThemeError {
+    line: usize,
+    message: String,
+}
+
+impl ParseThemeError {
+    fn new<M: fmt::Display>(line: usize, message: M) -> ParseThemeError {
+        ParseThemeError {
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    /// The line the error occurred on, 1 indexed
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseThemeError {}
+
+/// Removes a trailing comment, ignoring hashes in strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits an inline table on commas, ignoring commas in strings
+fn split_pairs(table: &str) -> Vec<&str> {
+    let mut pairs = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+    for (i, ch) in table.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                pairs.push(&table[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    pairs.push(&table[start..]);
+    pairs
+}
+
+/// Parses a `"quoted"` value
+fn unquote(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Some(&value[1..value.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Applies a single `key = "value"` pair to a role
+fn set_field(style: &mut ThemeStyle, pair: &str, line: usize) -> Result<(), ParseThemeError> {
+    let mut parts = pair.splitn(2, '=');
+    let key = parts.next().unwrap_or("").trim();
+    let value = parts
+        .next()
+        .and_then(unquote)
+        .ok_or_else(|| ParseThemeError::new(line, format!("expected `{} = \"...\"`", key)))?;
+    match key {
+        "fg" => style.fg = Some(value.parse().map_err(|e| ParseThemeError::new(line, e))?),
+        "bg" => style.bg = Some(value.parse().map_err(|e| ParseThemeError::new(line, e))?),
+        "style" => style.style = Some(value.parse().map_err(|e| ParseThemeError::new(line, e))?),
+        _ => return Err(ParseThemeError::new(line, format!("unknown key `{}`", key))),
+    }
+    Ok(())
+}
+
+impl FromStr for Theme {
+    type Err = ParseThemeError;
+
+    fn from_str(s: &str) -> Result<Theme, ParseThemeError> {
+        let mut theme = Theme::new();
+        let mut section: Option<String> = None;
+        for (i, line) in s.lines().enumerate() {
+            let line_no = i + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(ParseThemeError::new(line_no, "unclosed table header"));
+                }
+                let role = line[1..line.len() - 1].trim().trim_matches('"').to_owned();
+                theme.roles.entry(role.clone()).or_default();
+                section = Some(role);
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            if section.is_none() && value.starts_with('{') {
+                // An inline table for a whole role, only valid before the first table header
+                if !value.ends_with('}') {
+                    return Err(ParseThemeError::new(line_no, "unclosed inline table"));
+                }
+                let mut style = ThemeStyle::default();
+                for pair in split_pairs(&value[1..value.len() - 1]) {
+                    if !pair.trim().is_empty() {
+                        set_field(&mut style, pair, line_no)?;
+                    }
+                }
+                theme.roles.insert(key.trim_matches('"').to_owned(), style);
+            } else {
+                let role = section
+                    .as_ref()
+                    .ok_or_else(|| ParseThemeError::new(line_no, "key outside of a role table"))?;
+                let style = theme.roles.entry(role.clone()).or_default();
+                set_field(style, line, line_no)?;
+            }
+        }
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Theme;
+
+    #[test]
+    fn parse_errors() {
+        let err = "[title]\nfg = \"nope\"".parse::<Theme>().unwrap_err();
+        assert_eq!(err.line(), 2);
+        assert!("fg = \"red\"".parse::<Theme>().is_err());
+        assert!("[title]\nsize = \"3\"".parse::<Theme>().is_err());
+        assert!("[title]\nerror = { fg = \"red\" }"
+            .parse::<Theme>()
+            .is_err());
+    }
+}